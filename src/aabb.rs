@@ -1,5 +1,6 @@
 use crate::{math::Point, ray::Ray};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub struct AABB {
     minimum: Point,
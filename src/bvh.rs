@@ -5,6 +5,10 @@ use rand::{distributions::Standard, prelude::Distribution, Rng};
 use crate::{
     aabb::AABB,
     hittable::{Hittable, HittableList},
+    material::Material,
+    math::RenderRng,
+    scene_file::{ObjectDescription, SceneExporter},
+    stats,
 };
 
 pub struct BVHNode {
@@ -18,25 +22,42 @@ pub struct BVHNode {
 }
 
 impl BVHNode {
-    pub fn new(list: HittableList, start_time: f64, end_time: f64) -> Self {
-        BVHNode::new_inner(&mut list.as_raw(), start_time, end_time)
+    // None for an empty list, as there's nothing to bound
+    pub fn new<R: Rng + ?Sized>(
+        list: HittableList,
+        start_time: f64,
+        end_time: f64,
+        rng: &mut R,
+    ) -> Option<Self> {
+        let mut objects = list.into_raw();
+        if objects.is_empty() {
+            return None;
+        }
+
+        Some(BVHNode::new_inner(&mut objects, start_time, end_time, rng))
     }
 
-    fn new_inner(objects: &mut [Arc<dyn Hittable>], start_time: f64, end_time: f64) -> Self {
+    fn new_inner<R: Rng + ?Sized>(
+        objects: &mut [Arc<dyn Hittable>],
+        start_time: f64,
+        end_time: f64,
+        rng: &mut R,
+    ) -> Self {
         let (left, right) = match objects.len() {
             1 => (objects[0].clone(), objects[0].clone()),
-            2 => match box_compare(&objects[0], &objects[1], rand::random()) {
+            2 => match box_compare(&objects[0], &objects[1], rng.gen()) {
                 Ordering::Less => (objects[0].clone(), objects[1].clone()),
                 _ => (objects[1].clone(), objects[0].clone()),
             },
             _ => {
-                objects.sort_by(|a, b| box_compare(a, b, rand::random()));
+                let axis = rng.gen();
+                objects.sort_by(|a, b| box_compare(a, b, axis));
                 let mid = objects.len() / 2;
 
                 let (l, r) = objects.split_at_mut(mid);
                 (
-                    Arc::new(BVHNode::new_inner(l, start_time, end_time)) as Arc<dyn Hittable>,
-                    Arc::new(BVHNode::new_inner(r, start_time, end_time)) as Arc<dyn Hittable>,
+                    Arc::new(BVHNode::new_inner(l, start_time, end_time, rng)) as Arc<dyn Hittable>,
+                    Arc::new(BVHNode::new_inner(r, start_time, end_time, rng)) as Arc<dyn Hittable>,
                 )
             }
         };
@@ -61,23 +82,46 @@ impl Hittable for BVHNode {
         ray: &crate::ray::Ray,
         t_min: f64,
         t_max: f64,
+        rng: &mut RenderRng,
     ) -> Option<crate::hittable::HitRecord> {
+        stats::count_aabb_test();
         if !self.aabb.hit(ray, t_min, t_max) {
             return None;
         }
 
-        match self.left.hit(ray, t_min, t_max) {
-            Some(left_hit) => match self.right.hit(ray, t_min, left_hit.t) {
+        match self.left.hit(ray, t_min, t_max, rng) {
+            Some(left_hit) => match self.right.hit(ray, t_min, left_hit.t, rng) {
                 Some(right_hit) => Some(right_hit),
                 None => Some(left_hit),
             },
-            None => self.right.hit(ray, t_min, t_max),
+            None => self.right.hit(ray, t_min, t_max, rng),
         }
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
         Some(self.aabb.clone())
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        self.left.materials(materials);
+        self.right.materials(materials);
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        // Inner nodes are flattened into one list of the leaves
+        let mut objects = vec![];
+        let mut add = |child: &Arc<dyn Hittable>| match child.describe(exporter) {
+            ObjectDescription::Bvh { objects: leaves } => objects.extend(leaves),
+            leaf => objects.push(leaf),
+        };
+        add(&self.left);
+        // Nodes over a single object have it on both sides
+        if !Arc::ptr_eq(&self.left, &self.right) {
+            add(&self.right);
+        }
+
+        ObjectDescription::Bvh { objects }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -98,9 +142,9 @@ impl Distribution<Axis> for Standard {
     }
 }
 
-impl Into<usize> for Axis {
-    fn into(self) -> usize {
-        match self {
+impl From<Axis> for usize {
+    fn from(axis: Axis) -> Self {
+        match axis {
             Axis::X => 0,
             Axis::Y => 1,
             Axis::Z => 2,
@@ -1,8 +1,10 @@
 use nalgebra::Unit;
 
 use crate::{
-    math::{random_in_unit_disk, random_range, Vector},
+    math::{sample_unit_disk, Vector},
     ray::Ray,
+    sampler::Sampler,
+    scene_file::CameraDescription,
     Point,
 };
 
@@ -16,9 +18,16 @@ pub struct Camera {
     lens_radius: f64,
     shutter_open: f64,
     shutter_close: f64,
+    // The settings the camera was made with, for writing it out
+    look_at: Point,
+    view_up: Vector,
+    vfov: f64,
+    aperture: f64,
+    focus_distance: f64,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Point,
         lookat: Point,
@@ -55,17 +64,36 @@ impl Camera {
             lens_radius: aperture / 2.0,
             shutter_open,
             shutter_close,
+            look_at: lookat,
+            view_up,
+            vfov,
+            aperture,
+            focus_distance,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    // The aspect ratio isn't kept, as scene files take it from the image
+    pub fn describe(&self) -> CameraDescription {
+        CameraDescription {
+            look_from: self.origin,
+            look_at: self.look_at,
+            view_up: self.view_up,
+            vfov: self.vfov,
+            aperture: self.aperture,
+            focus_distance: self.focus_distance,
+            shutter_open: self.shutter_open,
+            shutter_close: self.shutter_close,
+        }
+    }
+
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * sample_unit_disk(sampler.get_2d());
         let offset = self.u * rd.x + self.v * rd.y;
 
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            random_range(self.shutter_open, self.shutter_close),
+            self.shutter_open + (self.shutter_close - self.shutter_open) * sampler.get_1d(),
         )
     }
 }
@@ -0,0 +1,237 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    film::Film,
+    integrator::{IntegratorKind, LightSampling, LuminanceClamp},
+    sampler::SamplerKind,
+};
+
+// Bump whenever the layout of a checkpoint changes
+const CHECKPOINT_VERSION: u32 = 10;
+const CHECKPOINT_MAGIC: [u8; 8] = *b"RTCHKPT\0";
+
+// Everything needed to rebuild the renderer a checkpoint was made with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointSettings {
+    pub scene: String,
+    pub params: Vec<i64>,
+    pub width: i64,
+    pub height: i64,
+    pub min_depth: i64,
+    pub max_depth: i64,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub integrator: IntegratorKind,
+    pub light_sampling: LightSampling,
+    pub clamp: LuminanceClamp,
+}
+
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    settings: &'a CheckpointSettings,
+    samples_per_pixel: u64,
+    film: &'a Film,
+}
+
+#[derive(Deserialize)]
+pub struct Checkpoint {
+    pub settings: CheckpointSettings,
+    // Samples per pixel already in the film
+    pub samples_per_pixel: u64,
+    pub film: Film,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    NotACheckpoint,
+    UnsupportedVersion(u32),
+    // The film's buffers don't all match the image size
+    WrongSize,
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{err}"),
+            CheckpointError::Encoding(err) => write!(f, "corrupt checkpoint: {err}"),
+            CheckpointError::NotACheckpoint => write!(f, "file is not a checkpoint"),
+            CheckpointError::UnsupportedVersion(version) => write!(
+                f,
+                "checkpoint version {version} isn't supported, expected {CHECKPOINT_VERSION}"
+            ),
+            CheckpointError::WrongSize => write!(
+                f,
+                "corrupt checkpoint: the image buffers don't match the image size"
+            ),
+        }
+    }
+}
+
+impl Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for CheckpointError {
+    fn from(err: bincode::Error) -> Self {
+        CheckpointError::Encoding(err)
+    }
+}
+
+// Like image snapshots, write next to the destination & move into place
+// so a render killed mid save doesn't lose the previous checkpoint
+pub fn save(
+    path: &Path,
+    settings: &CheckpointSettings,
+    samples_per_pixel: u64,
+    film: &Film,
+) -> Result<(), CheckpointError> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    let tmp_path = path.with_file_name(file_name);
+
+    let mut out = BufWriter::new(File::create(&tmp_path)?);
+    out.write_all(&CHECKPOINT_MAGIC)?;
+    out.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+    bincode::serialize_into(
+        &mut out,
+        &CheckpointRef {
+            settings,
+            samples_per_pixel,
+            film,
+        },
+    )?;
+    out.flush()?;
+    drop(out);
+
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Checkpoint, CheckpointError> {
+    let mut input = BufReader::new(File::open(path)?);
+
+    // Check the header before handing anything to bincode
+    let mut magic = [0; 8];
+    let mut version = [0; 4];
+    if input.read_exact(&mut magic).is_err() || magic != CHECKPOINT_MAGIC {
+        return Err(CheckpointError::NotACheckpoint);
+    }
+    input.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != CHECKPOINT_VERSION {
+        return Err(CheckpointError::UnsupportedVersion(version));
+    }
+
+    // A damaged or edited file can decode fine but have buffers which would be indexed past
+    let checkpoint: Checkpoint = bincode::deserialize_from(input)?;
+    let (width, height) = (checkpoint.settings.width, checkpoint.settings.height);
+    if width < 1 || height < 1 || !checkpoint.film.has_size(width as usize, height as usize) {
+        return Err(CheckpointError::WrongSize);
+    }
+
+    Ok(checkpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        film::{Aov, AovSample, Film},
+        filter::Filter,
+        integrator::{IntegratorKind, LightSampling, LuminanceClamp},
+        math::Color,
+        sampler::SamplerKind,
+    };
+
+    use super::{load, save, CheckpointError, CheckpointSettings};
+
+    fn settings() -> CheckpointSettings {
+        CheckpointSettings {
+            scene: "cornell_box".to_string(),
+            params: vec![1, 2],
+            width: 3,
+            height: 2,
+            min_depth: 3,
+            max_depth: 50,
+            seed: 7,
+            sampler: SamplerKind::Halton,
+            integrator: IntegratorKind::Path,
+            light_sampling: LightSampling::Mixture,
+            clamp: LuminanceClamp {
+                direct: Some(10.0),
+                indirect: None,
+            },
+        }
+    }
+
+    #[test]
+    pub fn round_trip() {
+        let settings = settings();
+        let mut film = Film::with_aovs(3, 2, Filter::default());
+        for mut tile in film.tiles(2, &[1; 6]) {
+            for (x, y, _) in tile.pixels() {
+                let aovs = AovSample {
+                    depth: x as f64,
+                    ..Default::default()
+                };
+                tile.add_sample(x, y, (0.5, 0.5), Color::new(x as f64, y as f64, 0.5), &aovs);
+            }
+            film.merge(&tile);
+        }
+        let path = std::env::temp_dir().join("rust_tracer_checkpoint_round_trip.chk");
+
+        save(&path, &settings, 1, &film).unwrap();
+        let checkpoint = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings, checkpoint.settings);
+        assert_eq!(1, checkpoint.samples_per_pixel);
+        assert_eq!(film.resolve().pixels(), checkpoint.film.resolve().pixels());
+        assert_eq!(film.sample_counts(), checkpoint.film.sample_counts());
+        assert_eq!(
+            film.resolve_aov(Aov::Depth).unwrap().pixels(),
+            checkpoint.film.resolve_aov(Aov::Depth).unwrap().pixels()
+        );
+    }
+
+    #[test]
+    pub fn reject_other_files() {
+        let path = std::env::temp_dir().join("rust_tracer_checkpoint_reject.chk");
+        std::fs::write(&path, b"P3\n1 1\n255\n0 0 0\n").unwrap();
+
+        let result = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(CheckpointError::NotACheckpoint)));
+    }
+
+    #[test]
+    pub fn reject_films_of_the_wrong_size() {
+        let settings = CheckpointSettings {
+            width: 4,
+            ..settings()
+        };
+        let film = Film::new(3, 2, Filter::default());
+        let path = std::env::temp_dir().join("rust_tracer_checkpoint_wrong_size.chk");
+
+        save(&path, &settings, 1, &film).unwrap();
+        let result = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(CheckpointError::WrongSize)));
+    }
+}
@@ -0,0 +1,342 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+
+use crate::{
+    denoise::DenoiseSettings,
+    film::Aov,
+    filter::{Filter, FilterKind},
+    integrator::{IntegratorKind, LightSampling, LuminanceClamp},
+    output::{ExrPrecision, OutputFormat, OutputSettings},
+    render::{AdaptiveSettings, RenderSettings},
+    sampler::SamplerKind,
+    tonemap::{DisplayTransform, ToneMapOperator, TransferFunction},
+};
+
+/// A simple CPU ray tracer
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// The scene to render, use --list-scenes to see what's available, or a .toml scene file
+    #[arg(short, long, default_value = "week_scene")]
+    pub scene: String,
+
+    /// Parameters passed to the scene generator in order, e.g. `-p 20 -p 1000` for week_scene
+    #[arg(short = 'p', long = "param", allow_negative_numbers = true)]
+    pub params: Vec<i64>,
+
+    /// Override the scene's image width. The height follows the scene's aspect ratio unless also set
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    pub width: Option<i64>,
+
+    /// Override the scene's image height
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    pub height: Option<i64>,
+
+    /// Override the scene's samples per pixel
+    #[arg(long)]
+    pub samples: Option<u64>,
+
+    /// Number of bounces before paths carrying little light may be ended early by Russian roulette.
+    /// Set it to --max-depth to always follow paths to the end
+    #[arg(long, default_value_t = 3)]
+    pub min_depth: i64,
+
+    /// Maximum number of bounces for each ray
+    #[arg(long, default_value_t = 50)]
+    pub max_depth: i64,
+
+    /// Number of render threads, defaults to one per logical CPU
+    #[arg(short, long)]
+    pub threads: Option<usize>,
+
+    /// Width & height in pixels of the tiles handed out to render threads
+    #[arg(long, default_value_t = 16)]
+    pub tile_size: usize,
+
+    /// Where to write the image. Writes to stdout if not set
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// The image format. Taken from the output file extension if not set, or ASCII PPM on stdout
+    #[arg(short, long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Channel precision for EXR output
+    #[arg(long, value_enum, default_value_t = ExrPrecision::Half)]
+    pub exr_precision: ExrPrecision,
+
+    /// Add an alpha channel to EXR output
+    #[arg(long)]
+    pub alpha: bool,
+
+    /// Exposure adjustment in stops, applied before tone mapping
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f64,
+
+    /// Tone mapping operator for 8 bit output
+    #[arg(long, value_enum, default_value_t = ToneMapOperator::Clamp)]
+    pub tonemap: ToneMapOperator,
+
+    /// The luminance mapped to white by the reinhard-extended & hable operators
+    #[arg(long, default_value_t = 4.0)]
+    pub white_point: f64,
+
+    /// Encoding applied after tone mapping for 8 bit output
+    #[arg(long, value_enum, default_value_t = TransferFunction::Srgb)]
+    pub transfer: TransferFunction,
+
+    /// Keep adding passes until this much time is up, e.g. `90`, `90s`, `5m` or `1.5h`, instead of
+    /// stopping at --samples. A pass is never started if it looks like it would run past the limit
+    #[arg(long, value_parser = parse_duration)]
+    pub time_limit: Option<Duration>,
+
+    /// Render in passes of this many samples per pixel, writing the output image after each pass
+    #[arg(long)]
+    pub progressive: Option<u64>,
+
+    /// With --progressive, write the output image at most every this many seconds. Images are only
+    /// written between passes, so with long passes the gaps can be longer
+    #[arg(long, requires = "progressive", value_parser = parse_positive)]
+    pub snapshot_interval: Option<f64>,
+
+    /// Seed for all random numbers, the same seed always gives the same image
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// How sample positions are picked for the pixel, lens, shutter & scatter directions
+    #[arg(long, value_enum, default_value_t = SamplerKind::Sobol)]
+    pub sampler: SamplerKind,
+
+    /// What to render: the path traced image, or a debug view of the first hit's normal, distance,
+    /// albedo, texture coordinates, face or the BVH nodes visited
+    #[arg(long, value_enum, default_value_t = IntegratorKind::Path)]
+    pub integrator: IntegratorKind,
+
+    /// How light is found at diffuse hits: sampling lights directly combined with scattering (mis),
+    /// picking either at random as one sample (mixture), or only by scattering (off)
+    #[arg(long, value_enum, default_value_t = LightSampling::Mis)]
+    pub light_sampling: LightSampling,
+
+    /// Scale down any sample whose light from one bounce is brighter than this luminance,
+    /// trading a little energy for fewer fireflies
+    #[arg(long, value_parser = parse_positive)]
+    pub clamp_direct: Option<f64>,
+
+    /// Like --clamp-direct, for the light from two or more bounces
+    #[arg(long, value_parser = parse_positive)]
+    pub clamp_indirect: Option<f64>,
+
+    /// Pixel reconstruction filter, weighting how much each sample counts towards the pixels around it.
+    /// box averages the samples in each pixel, the wider filters trade sharpness for less aliasing
+    #[arg(long, value_enum, default_value_t = FilterKind::Box)]
+    pub filter: FilterKind,
+
+    /// Radius of --filter in pixels [default: box 0.5, tent 1, gaussian 1.5, mitchell 2, lanczos 3]
+    #[arg(long)]
+    pub filter_radius: Option<f64>,
+
+    /// Spend the samples per pixel where the image is noisiest, stopping on pixels that have converged.
+    /// --samples becomes the mean samples per pixel over the image
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// With --adaptive, a pixel has converged once the standard error of its brightness is below this fraction of it
+    #[arg(long, default_value_t = 0.02, requires = "adaptive")]
+    pub adaptive_threshold: f64,
+
+    /// With --adaptive, samples every pixel gets before its error is checked
+    #[arg(long, default_value_t = 16, requires = "adaptive")]
+    pub min_samples: u64,
+
+    /// With --adaptive, the most samples any pixel gets [default: 8 times --samples]
+    #[arg(long, requires = "adaptive")]
+    pub max_samples: Option<u64>,
+
+    /// Write an image of how many samples each pixel took, brightest where it took the most
+    #[arg(long)]
+    pub sample_heatmap: Option<PathBuf>,
+
+    /// Extra passes to render alongside the image, e.g. `--aov normal,depth,albedo`.
+    /// EXR output stores them as layers of the output file, other formats write <name>_<aov>.<ext> next to it
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub aov: Vec<Aov>,
+
+    /// Write AOVs to separate files even for EXR output
+    #[arg(long)]
+    pub aov_files: bool,
+
+    /// Smooth out noise in the finished image, guided by the albedo, normals & depth of the first hits
+    #[arg(long)]
+    pub denoise: bool,
+
+    /// With --denoise, how many times to filter. Each pass reaches twice as far as the last
+    #[arg(long, default_value_t = 5, requires = "denoise")]
+    pub denoise_iterations: usize,
+
+    /// Also write the stats printed at the end of the render to this file as JSON
+    #[arg(long)]
+    pub stats_json: Option<PathBuf>,
+
+    /// Periodically save the render's progress to this file, so it can be picked up with --resume
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoint saves. A checkpoint is always saved when the render finishes
+    #[arg(long, default_value_t = 300.0, value_parser = parse_positive)]
+    pub checkpoint_interval: f64,
+
+    /// Carry on a render from a checkpoint, adding samples until --samples is reached.
+    /// The scene, image size & max depth are taken from the checkpoint
+    #[arg(long)]
+    pub resume: Option<PathBuf>,
+
+    /// List the available scenes and their parameters, then exit
+    #[arg(long)]
+    pub list_scenes: bool,
+
+    /// Write the scene out as a .toml scene file to edit, then exit without rendering
+    #[arg(long)]
+    pub export_scene: Option<PathBuf>,
+}
+
+impl Cli {
+    pub fn render_settings(&self) -> RenderSettings {
+        RenderSettings {
+            integrator: self.integrator,
+            min_depth: self.min_depth,
+            max_depth: self.max_depth,
+            tile_size: self.tile_size,
+            seed: self.seed,
+            sampler: self.sampler,
+            light_sampling: self.light_sampling,
+            clamp: LuminanceClamp {
+                direct: self.clamp_direct,
+                indirect: self.clamp_indirect,
+            },
+            filter: Filter::new(self.filter, self.filter_radius),
+            // Filled in once the scene's been picked
+            samples_per_pixel: 0,
+            time_limit: self.time_limit,
+        }
+    }
+
+    pub fn adaptive_settings(&self, samples_per_pixel: u64) -> Option<AdaptiveSettings> {
+        self.adaptive.then(|| AdaptiveSettings {
+            min_samples: self.min_samples,
+            max_samples: self.max_samples.unwrap_or(samples_per_pixel * 8),
+            threshold: self.adaptive_threshold,
+        })
+    }
+
+    pub fn denoise_settings(&self) -> Option<DenoiseSettings> {
+        self.denoise.then_some(DenoiseSettings {
+            iterations: self.denoise_iterations,
+        })
+    }
+
+    pub fn output_settings(&self) -> OutputSettings {
+        OutputSettings {
+            format: self.format,
+            exr_precision: self.exr_precision,
+            alpha: self.alpha,
+            display: DisplayTransform {
+                exposure: self.exposure,
+                operator: self.tonemap,
+                white_point: self.white_point,
+                transfer: self.transfer,
+            },
+        }
+    }
+}
+
+// Seconds, optionally with an s, m or h suffix
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1.0),
+        Some((i, 'm')) => (&value[..i], 60.0),
+        Some((i, 'h')) => (&value[..i], 3600.0),
+        _ => (value, 1.0),
+    };
+    let seconds = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("'{value}' isn't a duration like 90, 90s, 5m or 1.5h"))?;
+
+    Duration::try_from_secs_f64(seconds * unit).map_err(|err| err.to_string())
+}
+
+// A finite number above zero
+fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!("'{value}' isn't a number above zero")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::{parse_duration, Cli};
+
+    #[test]
+    pub fn durations_take_units() {
+        assert_eq!(Duration::from_secs(90), parse_duration("90").unwrap());
+        assert_eq!(Duration::from_secs(90), parse_duration("90s").unwrap());
+        assert_eq!(Duration::from_secs(300), parse_duration("5m").unwrap());
+        assert_eq!(Duration::from_secs(5400), parse_duration("1.5h").unwrap());
+        assert_eq!(Duration::from_secs(300), parse_duration("  5m").unwrap());
+        assert_eq!(Duration::from_secs(90), parse_duration(" 90s \n").unwrap());
+        assert!(parse_duration("5 minutes").is_err());
+        assert!(parse_duration("-1").is_err());
+    }
+
+    #[test]
+    pub fn image_sizes_are_at_least_one() {
+        for arg in ["--width=-4", "--width=0", "--height=0"] {
+            assert!(Cli::try_parse_from(["rust-tracer", arg]).is_err(), "{arg}");
+        }
+        let cli = Cli::try_parse_from(["rust-tracer", "--width=1", "--height=1"]).unwrap();
+        assert_eq!((Some(1), Some(1)), (cli.width, cli.height));
+    }
+
+    #[test]
+    pub fn intervals_are_positive() {
+        for arg in [
+            "--checkpoint-interval=-1",
+            "--checkpoint-interval=0",
+            "--checkpoint-interval=NaN",
+            "--checkpoint-interval=inf",
+        ] {
+            assert!(Cli::try_parse_from(["rust-tracer", arg]).is_err(), "{arg}");
+        }
+        for arg in ["--snapshot-interval=-1", "--snapshot-interval=NaN"] {
+            let args = ["rust-tracer", "--progressive=4", arg];
+            assert!(Cli::try_parse_from(args).is_err(), "{arg}");
+        }
+        let args = ["rust-tracer", "--progressive=4", "--snapshot-interval=2"];
+        assert_eq!(
+            Some(2.0),
+            Cli::try_parse_from(args).unwrap().snapshot_interval
+        );
+        let cli = Cli::try_parse_from(["rust-tracer", "--checkpoint-interval=0.5"]).unwrap();
+        assert_eq!(0.5, cli.checkpoint_interval);
+    }
+
+    #[test]
+    pub fn clamps_are_positive() {
+        for arg in [
+            "--clamp-direct=-1",
+            "--clamp-direct=0",
+            "--clamp-indirect=NaN",
+        ] {
+            assert!(Cli::try_parse_from(["rust-tracer", arg]).is_err(), "{arg}");
+        }
+        let cli = Cli::try_parse_from(["rust-tracer", "--clamp-indirect=10"]).unwrap();
+        assert_eq!(Some(10.0), cli.clamp_indirect);
+    }
+}
@@ -0,0 +1,181 @@
+use rayon::prelude::*;
+
+use crate::{
+    film::{Aov, Film},
+    framebuffer::FrameBuffer,
+    math::{Color, Vector},
+    tonemap::luminance,
+};
+
+// Weights of the 5 taps along each axis, a B3 spline
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Albedo channels darker than this aren't divided out, as there's no lighting left to recover
+const ALBEDO_FLOOR: f64 = 0.01;
+
+// How many standard errors neighbouring lighting may differ by before it stops being averaged in
+const COLOR_SIGMA: f64 = 4.0;
+// Cosine power applied to the angle between normals
+const NORMAL_POWER: i32 = 64;
+// How far apart depths may be, relative to the pixel's depth & per pixel of distance
+const DEPTH_SIGMA: f64 = 0.02;
+const ALBEDO_SIGMA: f64 = 0.1;
+
+pub struct DenoiseSettings {
+    pub iterations: usize,
+}
+
+// Edge avoiding À-Trous wavelet filter (Dammertz et al. 2010), with the color weights scaled by
+// each pixel's variance like SVGF (Schied et al. 2017). Each iteration blurs with a 5x5 kernel
+// spread twice as wide as the last, skipping neighbours whose normal, depth or albedo differ
+// from the pixel's. The albedo is divided out first so only the lighting is blurred &
+// textures stay sharp. None if the film wasn't rendered with AOVs
+pub fn denoise(film: &Film, settings: &DenoiseSettings) -> Option<FrameBuffer> {
+    let image = film.resolve();
+    let albedo = film.resolve_aov(Aov::Albedo)?;
+    let depth = film.resolve_aov(Aov::Depth)?;
+    let normals: Vec<Vector> = film
+        .resolve_aov(Aov::Normal)?
+        .pixels()
+        .iter()
+        .map(|normal| normal.try_normalize(0.0).unwrap_or_default())
+        .collect();
+    let (width, height) = (image.width(), image.height());
+
+    let albedo_divisor: Vec<Color> = albedo
+        .pixels()
+        .iter()
+        .map(|albedo| albedo.map(|c| if c > ALBEDO_FLOOR { c } else { 1.0 }))
+        .collect();
+    let mut lighting: Vec<Color> = image
+        .pixels()
+        .iter()
+        .zip(&albedo_divisor)
+        .map(|(color, divisor)| color.component_div(divisor))
+        .collect();
+    let mut variance: Vec<f64> = albedo_divisor
+        .iter()
+        .enumerate()
+        .map(|(i, divisor)| film.mean_variance(i) / luminance(*divisor).powi(2))
+        .collect();
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+
+        (lighting, variance) = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let color_scale = COLOR_SIGMA.powi(2) * variance[i] + 1e-6;
+
+                let mut sum = Color::new(0.0, 0.0, 0.0);
+                let mut variance_sum = 0.0;
+                let mut total_weight = 0.0;
+                for (dy, ky) in KERNEL.iter().enumerate() {
+                    for (dx, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (dx as isize - 2) * step;
+                        let qy = y as isize + (dy as isize - 2) * step;
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let j = qy as usize * width + qx as usize;
+                        let offset = ((qx - x as isize).abs() + (qy - y as isize).abs()) as f64;
+
+                        let color_weight =
+                            (-(lighting[j] - lighting[i]).norm_squared() / color_scale).exp();
+                        // Misses have no normal & only blend with each other
+                        let normal_weight = if normals[i] == normals[j] {
+                            1.0
+                        } else {
+                            normals[i].dot(&normals[j]).max(0.0).powi(NORMAL_POWER)
+                        };
+                        let depth_difference = (depth.pixels()[j].x - depth.pixels()[i].x).abs();
+                        let depth_weight = (-depth_difference
+                            / (DEPTH_SIGMA * depth.pixels()[i].x * offset + 1e-6))
+                            .exp();
+                        let albedo_difference = albedo.pixels()[j] - albedo.pixels()[i];
+                        let albedo_weight =
+                            (-albedo_difference.norm_squared() / ALBEDO_SIGMA.powi(2)).exp();
+
+                        let weight =
+                            kx * ky * color_weight * normal_weight * depth_weight * albedo_weight;
+                        if weight > 0.0 {
+                            sum += weight * lighting[j];
+                            variance_sum += weight * weight * variance[j];
+                            total_weight += weight;
+                        }
+                    }
+                }
+
+                // The center always has a weight, so this can't divide by zero
+                (
+                    sum / total_weight,
+                    variance_sum / (total_weight * total_weight),
+                )
+            })
+            .unzip();
+    }
+
+    let pixels = lighting
+        .iter()
+        .zip(&albedo_divisor)
+        .map(|(lighting, divisor)| lighting.component_mul(divisor))
+        .collect();
+
+    Some(FrameBuffer::from_pixels(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        film::{AovSample, Film},
+        filter::Filter,
+        math::{Color, RenderRng, Vector},
+    };
+
+    use super::{denoise, DenoiseSettings};
+
+    #[test]
+    pub fn smooths_noise_but_keeps_edges() {
+        // Flat lighting on a wall whose left half is darker than its right
+        let mut film = Film::with_aovs(16, 16, Filter::default());
+        let mut rng = RenderRng::seed_from_u64(0);
+        let albedo = |x: usize| match x < 8 {
+            true => Color::new(0.2, 0.2, 0.2),
+            false => Color::new(0.8, 0.8, 0.8),
+        };
+        for mut tile in film.tiles(16, &[4; 16 * 16]) {
+            for (x, y, samples) in tile.pixels() {
+                let aovs = AovSample {
+                    normal: Vector::new(0.0, 0.0, 1.0),
+                    depth: 1.0,
+                    albedo: albedo(x),
+                    ..Default::default()
+                };
+                for _ in samples {
+                    let color = albedo(x) * rng.gen_range(0.0..2.0);
+                    tile.add_sample(x, y, (0.5, 0.5), color, &aovs);
+                }
+            }
+            film.merge(&tile);
+        }
+
+        let error = |image: &[Color]| {
+            image
+                .iter()
+                .enumerate()
+                .map(|(i, pixel)| (pixel - albedo(i % 16)).norm_squared())
+                .sum::<f64>()
+        };
+        let noisy = film.resolve();
+        let denoised = denoise(&film, &DenoiseSettings { iterations: 5 }).unwrap();
+
+        assert!(error(denoised.pixels()) < 0.25 * error(noisy.pixels()));
+        // Either side of the edge keeps its own brightness
+        let column = |x: usize| (0..16).map(|y| denoised.get(x, y).x).sum::<f64>() / 16.0;
+        assert!((column(7) - 0.2).abs() < 0.02, "{}", column(7));
+        assert!((column(8) - 0.8).abs() < 0.08, "{}", column(8));
+    }
+}
@@ -0,0 +1,745 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    filter::Filter,
+    framebuffer::FrameBuffer,
+    math::{Color, Vector},
+    tonemap::{heat_color, luminance},
+};
+
+// Pixels darker than this are treated as this bright when estimating relative error,
+// otherwise tiny amounts of noise in near black pixels would look huge
+const ERROR_MEAN_FLOOR: f64 = 0.01;
+
+// Pixels whose filter weights add up to less than this fall back to averaging their own samples,
+// as negative lobes could leave them dividing by almost nothing
+const FILTER_WEIGHT_FLOOR: f64 = 1e-6;
+
+// Arbitrary output variables, extra images rendered alongside the beauty image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Aov {
+    // World space normal at the first hit
+    Normal,
+    // Distance to the first hit
+    Depth,
+    Albedo,
+    // Which of the scene's top level objects was hit first, counting from 1
+    ObjectId,
+    // Which material was hit first, counting from 1 in the order they appear in the scene
+    MaterialId,
+    // Light emitted by whatever the camera sees directly, including the background
+    Emission,
+    // Light which reached the camera after one bounce
+    Direct,
+    // Light which reached the camera after more than one bounce
+    Indirect,
+    SampleCount,
+}
+
+// What a single sample saw, for the AOVs.
+// Emission, direct & indirect add up to the sample's color
+#[derive(Debug, Clone, PartialEq)]
+pub struct AovSample {
+    pub normal: Vector,
+    pub depth: f64,
+    pub albedo: Color,
+    // Zero where nothing was hit
+    pub object_id: u32,
+    pub material_id: u32,
+    pub emission: Color,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self {
+            normal: Vector::new(0.0, 0.0, 0.0),
+            depth: 0.0,
+            albedo: Color::new(0.0, 0.0, 0.0),
+            object_id: 0,
+            material_id: 0,
+            emission: Color::new(0.0, 0.0, 0.0),
+            direct: Color::new(0.0, 0.0, 0.0),
+            indirect: Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Accumulated AOVs for a film or tile. IDs can't be averaged, so they come from each pixel's
+// first sample
+#[derive(Serialize, Deserialize)]
+struct AovBuffers {
+    normal: Vec<Vector>,
+    depth: Vec<f64>,
+    albedo: Vec<Color>,
+    object_id: Vec<u32>,
+    material_id: Vec<u32>,
+    emission: Vec<Color>,
+    direct: Vec<Color>,
+    indirect: Vec<Color>,
+}
+
+impl AovBuffers {
+    fn new(pixels: usize) -> Self {
+        Self {
+            normal: vec![Vector::new(0.0, 0.0, 0.0); pixels],
+            depth: vec![0.0; pixels],
+            albedo: vec![Color::new(0.0, 0.0, 0.0); pixels],
+            object_id: vec![0; pixels],
+            material_id: vec![0; pixels],
+            emission: vec![Color::new(0.0, 0.0, 0.0); pixels],
+            direct: vec![Color::new(0.0, 0.0, 0.0); pixels],
+            indirect: vec![Color::new(0.0, 0.0, 0.0); pixels],
+        }
+    }
+
+    fn has_pixels(&self, pixels: usize) -> bool {
+        [
+            self.normal.len(),
+            self.depth.len(),
+            self.albedo.len(),
+            self.object_id.len(),
+            self.material_id.len(),
+            self.emission.len(),
+            self.direct.len(),
+            self.indirect.len(),
+        ]
+        .iter()
+        .all(|&len| len == pixels)
+    }
+
+    fn add(&mut self, index: usize, sample: &AovSample, first: bool) {
+        self.normal[index] += sample.normal;
+        self.depth[index] += sample.depth;
+        self.albedo[index] += sample.albedo;
+        self.emission[index] += sample.emission;
+        self.direct[index] += sample.direct;
+        self.indirect[index] += sample.indirect;
+
+        if first {
+            self.object_id[index] = sample.object_id;
+            self.material_id[index] = sample.material_id;
+        }
+    }
+
+    fn merge(&mut self, index: usize, other: &AovBuffers, other_index: usize, first: bool) {
+        self.normal[index] += other.normal[other_index];
+        self.depth[index] += other.depth[other_index];
+        self.albedo[index] += other.albedo[other_index];
+        self.emission[index] += other.emission[other_index];
+        self.direct[index] += other.direct[other_index];
+        self.indirect[index] += other.indirect[other_index];
+
+        if first {
+            self.object_id[index] = other.object_id[other_index];
+            self.material_id[index] = other.material_id[other_index];
+        }
+    }
+}
+
+// Accumulates radiance samples for the whole image, rows are stored top first.
+// Each sample is splatted into the pixels around it by the reconstruction filter,
+// while the plain sums & counts of the pixel it was taken in drive the error estimates
+#[derive(Serialize, Deserialize)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    filtered: Vec<Color>,
+    filter_weights: Vec<f64>,
+    sums: Vec<Color>,
+    // Sums of each sample's squared luminance, for estimating the variance of each pixel
+    squared_luminance: Vec<f64>,
+    samples: Vec<u64>,
+    // Samples thrown away for being NaN or infinite, which still use up their sample index
+    dropped: Vec<u64>,
+    aovs: Option<AovBuffers>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            filtered: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            filter_weights: vec![0.0; width * height],
+            sums: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            squared_luminance: vec![0.0; width * height],
+            samples: vec![0; width * height],
+            dropped: vec![0; width * height],
+            aovs: None,
+        }
+    }
+
+    // A film which also accumulates the AOVs
+    pub fn with_aovs(width: usize, height: usize, filter: Filter) -> Self {
+        Self {
+            aovs: Some(AovBuffers::new(width * height)),
+            ..Self::new(width, height, filter)
+        }
+    }
+
+    #[inline]
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    // Whether the film is width by height with a value for every pixel in each buffer, which a
+    // film read back from a file may not be
+    #[inline]
+    pub fn has_size(&self, width: usize, height: usize) -> bool {
+        let Some(pixels) = width.checked_mul(height) else {
+            return false;
+        };
+
+        self.width == width
+            && self.height == height
+            && [
+                self.filtered.len(),
+                self.filter_weights.len(),
+                self.sums.len(),
+                self.squared_luminance.len(),
+                self.samples.len(),
+                self.dropped.len(),
+            ]
+            .iter()
+            .all(|&len| len == pixels)
+            && self
+                .aovs
+                .as_ref()
+                .is_none_or(|aovs| aovs.has_pixels(pixels))
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    // Samples kept in each pixel, row-major
+    #[cfg(test)]
+    pub fn sample_counts(&self) -> &[u64] {
+        &self.samples
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.samples.iter().sum()
+    }
+
+    // Samples taken in each pixel including the dropped ones, row-major
+    pub fn taken_counts(&self) -> Vec<u64> {
+        self.samples
+            .iter()
+            .zip(&self.dropped)
+            .map(|(samples, dropped)| samples + dropped)
+            .collect()
+    }
+
+    // Film space coordinates & counts of the pixels which had samples dropped
+    pub fn dropped_samples(&self) -> Vec<(usize, usize, u64)> {
+        self.dropped
+            .iter()
+            .enumerate()
+            .filter(|(_, &dropped)| dropped > 0)
+            .map(|(i, &dropped)| (i % self.width, i / self.width, dropped))
+            .collect()
+    }
+
+    // The variance of the pixel's mean luminance, i.e. its squared standard error
+    pub fn mean_variance(&self, index: usize) -> f64 {
+        let n = self.samples[index];
+        if n < 2 {
+            return f64::INFINITY;
+        }
+
+        let n = n as f64;
+        let mean = luminance(self.sums[index]) / n;
+        let variance = (self.squared_luminance[index] / n - mean * mean).max(0.0) * n / (n - 1.0);
+
+        variance / n
+    }
+
+    // The standard error of the pixel's mean luminance, relative to the mean
+    pub fn relative_error(&self, index: usize) -> f64 {
+        let mean = match self.samples[index] {
+            0 => 0.0,
+            n => luminance(self.sums[index]) / n as f64,
+        };
+
+        self.mean_variance(index).sqrt() / mean.max(ERROR_MEAN_FLOOR)
+    }
+
+    // Split the image into tiles which cover every pixel exactly once.
+    // plan holds the number of samples to add to each pixel, tiles with nothing to do are skipped
+    pub fn tiles(&self, tile_size: usize, plan: &[u64]) -> Vec<Tile> {
+        let tile_size = tile_size.max(1);
+        let mut tiles = vec![];
+
+        for y0 in (0..self.height).step_by(tile_size) {
+            for x0 in (0..self.width).step_by(tile_size) {
+                let width = tile_size.min(self.width - x0);
+                let height = tile_size.min(self.height - y0);
+
+                let mut first_sample = Vec::with_capacity(width * height);
+                let mut planned = Vec::with_capacity(width * height);
+                for y in y0..y0 + height {
+                    for x in x0..x0 + width {
+                        // New samples carry on from the ones already in the pixel
+                        let index = y * self.width + x;
+                        first_sample.push(self.samples[index] + self.dropped[index]);
+                        planned.push(plan[y * self.width + x]);
+                    }
+                }
+
+                if planned.iter().any(|&samples| samples > 0) {
+                    let mut tile = Tile::new(self, x0, y0, width, height, first_sample, planned);
+                    if self.has_aovs() {
+                        tile.aovs = Some(AovBuffers::new(width * height));
+                    }
+                    tiles.push(tile);
+                }
+            }
+        }
+
+        tiles
+    }
+
+    pub fn merge(&mut self, tile: &Tile) {
+        for y in 0..tile.height {
+            for x in 0..tile.width {
+                let film_index = (tile.y0 + y) * self.width + tile.x0 + x;
+                let tile_index = y * tile.width + x;
+
+                if let (Some(aovs), Some(tile_aovs)) = (&mut self.aovs, &tile.aovs) {
+                    let first = self.samples[film_index] == 0;
+                    aovs.merge(film_index, tile_aovs, tile_index, first);
+                }
+
+                self.sums[film_index] += tile.sums[tile_index];
+                self.squared_luminance[film_index] += tile.squared_luminance[tile_index];
+                self.samples[film_index] += tile.samples[tile_index];
+                self.dropped[film_index] += tile.dropped[tile_index];
+            }
+        }
+
+        for y in 0..tile.splat_height {
+            for x in 0..tile.splat_width {
+                let film_index = (tile.splat_y0 + y) * self.width + tile.splat_x0 + x;
+                let tile_index = y * tile.splat_width + x;
+
+                self.filtered[film_index] += tile.filtered[tile_index];
+                self.filter_weights[film_index] += tile.filter_weights[tile_index];
+            }
+        }
+    }
+
+    // The filtered average of the samples around each pixel
+    pub fn resolve(&self) -> FrameBuffer {
+        let pixels = (0..self.pixel_count())
+            .map(|i| match self.samples[i] {
+                _ if self.filter_weights[i] > FILTER_WEIGHT_FLOOR => {
+                    self.filtered[i] / self.filter_weights[i]
+                }
+                0 => Color::new(0.0, 0.0, 0.0),
+                n => self.sums[i] / n as f64,
+            })
+            .collect();
+
+        FrameBuffer::from_pixels(self.width, self.height, pixels)
+    }
+
+    // Average an AOV over each pixel's samples, or None if the film doesn't have them.
+    // Single values are copied into every channel
+    pub fn resolve_aov(&self, aov: Aov) -> Option<FrameBuffer> {
+        let aovs = self.aovs.as_ref()?;
+        let average = |sum: Color, samples: u64| match samples {
+            0 => Color::new(0.0, 0.0, 0.0),
+            n => sum / n as f64,
+        };
+        let single = |value: f64| Color::new(value, value, value);
+
+        let pixels = (0..self.pixel_count())
+            .map(|i| {
+                let samples = self.samples[i];
+                match aov {
+                    Aov::Normal => average(aovs.normal[i], samples),
+                    Aov::Depth => average(single(aovs.depth[i]), samples),
+                    Aov::Albedo => average(aovs.albedo[i], samples),
+                    Aov::ObjectId => single(aovs.object_id[i] as f64),
+                    Aov::MaterialId => single(aovs.material_id[i] as f64),
+                    Aov::Emission => average(aovs.emission[i], samples),
+                    Aov::Direct => average(aovs.direct[i], samples),
+                    Aov::Indirect => average(aovs.indirect[i], samples),
+                    Aov::SampleCount => single(samples as f64),
+                }
+            })
+            .collect();
+
+        Some(FrameBuffer::from_pixels(self.width, self.height, pixels))
+    }
+
+    // Color each pixel by how many samples it took, relative to the busiest pixel
+    pub fn sample_heatmap(&self) -> FrameBuffer {
+        let max = self
+            .samples
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        let pixels = self
+            .samples
+            .iter()
+            .map(|&samples| heat_color(samples as f64 / max as f64))
+            .collect();
+
+        FrameBuffer::from_pixels(self.width, self.height, pixels)
+    }
+}
+
+// A rectangular part of the film which a single worker renders into
+pub struct Tile {
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+    first_sample: Vec<u64>,
+    planned: Vec<u64>,
+    sums: Vec<Color>,
+    squared_luminance: Vec<f64>,
+    samples: Vec<u64>,
+    dropped: Vec<u64>,
+    aovs: Option<AovBuffers>,
+    // The tile plus the filter's margin, clipped to the film
+    filter: Filter,
+    splat_x0: usize,
+    splat_y0: usize,
+    splat_width: usize,
+    splat_height: usize,
+    filtered: Vec<Color>,
+    filter_weights: Vec<f64>,
+}
+
+impl Tile {
+    fn new(
+        film: &Film,
+        x0: usize,
+        y0: usize,
+        width: usize,
+        height: usize,
+        first_sample: Vec<u64>,
+        planned: Vec<u64>,
+    ) -> Self {
+        // Splats can land up to the filter's margin outside the tile
+        let margin = film.filter.margin();
+        let splat_x0 = x0.saturating_sub(margin);
+        let splat_y0 = y0.saturating_sub(margin);
+        let splat_width = (x0 + width + margin).min(film.width) - splat_x0;
+        let splat_height = (y0 + height + margin).min(film.height) - splat_y0;
+
+        Self {
+            x0,
+            y0,
+            width,
+            height,
+            first_sample,
+            planned,
+            sums: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            squared_luminance: vec![0.0; width * height],
+            samples: vec![0; width * height],
+            dropped: vec![0; width * height],
+            aovs: None,
+            filter: film.filter,
+            splat_x0,
+            splat_y0,
+            splat_width,
+            splat_height,
+            filtered: vec![Color::new(0.0, 0.0, 0.0); splat_width * splat_height],
+            filter_weights: vec![0.0; splat_width * splat_height],
+        }
+    }
+
+    // Film space pixel coordinates covered by the tile, with the indices of the samples to take
+    pub fn pixels(&self) -> Vec<(usize, usize, std::ops::Range<u64>)> {
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = y * self.width + x;
+                let first = self.first_sample[index];
+
+                pixels.push((self.x0 + x, self.y0 + y, first..first + self.planned[index]));
+            }
+        }
+
+        pixels
+    }
+
+    // Takes film space coordinates, with the sample's position inside the pixel
+    // as an offset from its top left corner. NaN or infinite samples are counted & dropped,
+    // as one would poison every pixel it's splatted into
+    #[inline]
+    pub fn add_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        (dx, dy): (f64, f64),
+        color: Color,
+        aovs: &AovSample,
+    ) {
+        let index = (y - self.y0) * self.width + x - self.x0;
+        if !color.iter().all(|c| c.is_finite()) {
+            self.dropped[index] += 1;
+            return;
+        }
+
+        // The film only takes the IDs if it had no samples of its own
+        if let Some(tile_aovs) = &mut self.aovs {
+            let first = self.samples[index] == 0;
+            tile_aovs.add(index, aovs, first);
+        }
+
+        self.sums[index] += color;
+        self.squared_luminance[index] += luminance(color).powi(2);
+        self.samples[index] += 1;
+
+        // Splat into every pixel whose center is within [-radius, radius) of the sample
+        let (sample_x, sample_y) = (x as f64 + dx, y as f64 + dy);
+        let radius = self.filter.radius();
+        let pixels = |position: f64, start: usize, length: usize| {
+            let first = ((position - radius - 0.5).floor() + 1.0).max(start as f64) as usize;
+            let last = ((position + radius - 0.5).floor() as usize).min(start + length - 1);
+            first..=last
+        };
+
+        for py in pixels(sample_y, self.splat_y0, self.splat_height) {
+            for px in pixels(sample_x, self.splat_x0, self.splat_width) {
+                let weight = self
+                    .filter
+                    .weight(sample_x - (px as f64 + 0.5), sample_y - (py as f64 + 0.5));
+                let index = (py - self.splat_y0) * self.splat_width + px - self.splat_x0;
+
+                self.filtered[index] += weight * color;
+                self.filter_weights[index] += weight;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        filter::{Filter, FilterKind},
+        math::Color,
+    };
+
+    use super::{Aov, AovSample, Film};
+
+    #[test]
+    pub fn tiles_cover_film_once() {
+        let film = Film::new(37, 21, Filter::default());
+        let mut covered = vec![0; 37 * 21];
+
+        for tile in film.tiles(16, &[1; 37 * 21]) {
+            for (x, y, _) in tile.pixels() {
+                covered[y * 37 + x] += 1;
+            }
+        }
+
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    pub fn tiles_skip_pixels_without_samples() {
+        let film = Film::new(4, 4, Filter::default());
+        let mut plan = [0; 16];
+        plan[5] = 3;
+
+        let tiles = film.tiles(2, &plan);
+
+        assert_eq!(1, tiles.len());
+        let samples: Vec<u64> = tiles[0]
+            .pixels()
+            .iter()
+            .map(|(_, _, samples)| samples.end - samples.start)
+            .collect();
+        assert_eq!(vec![0, 0, 0, 3], samples);
+    }
+
+    #[test]
+    pub fn resolve_averages_samples() {
+        let mut film = Film::new(2, 2, Filter::default());
+
+        for mut tile in film.tiles(1, &[2; 4]) {
+            for (x, y, _) in tile.pixels() {
+                tile.add_sample(
+                    x,
+                    y,
+                    (0.5, 0.5),
+                    Color::new(x as f64, y as f64, 1.0),
+                    &AovSample::default(),
+                );
+                tile.add_sample(
+                    x,
+                    y,
+                    (0.5, 0.5),
+                    Color::new(x as f64, y as f64, 0.0),
+                    &AovSample::default(),
+                );
+            }
+            film.merge(&tile);
+        }
+
+        let image = film.resolve();
+        assert_eq!(Color::new(1.0, 0.0, 0.5), image.get(1, 0));
+        assert_eq!(Color::new(0.0, 1.0, 0.5), image.get(0, 1));
+
+        // Later samples carry on from the ones already taken
+        let tiles = film.tiles(2, &[1; 4]);
+        assert_eq!(2..3, tiles[0].pixels()[3].2);
+    }
+
+    #[test]
+    pub fn relative_error_tracks_noise() {
+        let mut film = Film::new(2, 1, Filter::default());
+
+        for mut tile in film.tiles(2, &[4; 2]) {
+            for i in 0..4 {
+                // A flat pixel & a noisy one with the same mean
+                tile.add_sample(
+                    0,
+                    0,
+                    (0.5, 0.5),
+                    Color::new(0.5, 0.5, 0.5),
+                    &AovSample::default(),
+                );
+                tile.add_sample(
+                    1,
+                    0,
+                    (0.5, 0.5),
+                    Color::new(1.0, 1.0, 1.0) * (i % 2) as f64,
+                    &AovSample::default(),
+                );
+            }
+            film.merge(&tile);
+        }
+
+        assert_eq!(0.0, film.relative_error(0));
+        assert!(film.relative_error(1) > 0.5);
+    }
+
+    #[test]
+    pub fn aovs_average_except_ids() {
+        let mut film = Film::with_aovs(1, 1, Filter::default());
+
+        for depth in [1.0, 3.0] {
+            for mut tile in film.tiles(1, &[1]) {
+                let aovs = AovSample {
+                    depth,
+                    object_id: depth as u32,
+                    ..Default::default()
+                };
+                tile.add_sample(0, 0, (0.5, 0.5), Color::new(0.0, 0.0, 0.0), &aovs);
+                film.merge(&tile);
+            }
+        }
+
+        assert_eq!(2.0, film.resolve_aov(Aov::Depth).unwrap().get(0, 0).x);
+        assert_eq!(1.0, film.resolve_aov(Aov::ObjectId).unwrap().get(0, 0).x);
+        assert_eq!(2.0, film.resolve_aov(Aov::SampleCount).unwrap().get(0, 0).x);
+        assert!(Film::new(1, 1, Filter::default())
+            .resolve_aov(Aov::Depth)
+            .is_none());
+    }
+
+    #[test]
+    pub fn wide_filters_splat_into_neighbours() {
+        // A bright sample near the right edge of the middle pixel, with tiles of one pixel
+        // so the splat has to cross into the neighbouring tile
+        let mut film = Film::new(3, 1, Filter::new(FilterKind::Tent, Some(1.0)));
+        for mut tile in film.tiles(1, &[1; 3]) {
+            for (x, y, _) in tile.pixels() {
+                match x {
+                    1 => tile.add_sample(
+                        x,
+                        y,
+                        (0.75, 0.5),
+                        Color::new(1.0, 1.0, 1.0),
+                        &AovSample::default(),
+                    ),
+                    _ => tile.add_sample(
+                        x,
+                        y,
+                        (0.5, 0.5),
+                        Color::new(0.0, 0.0, 0.0),
+                        &AovSample::default(),
+                    ),
+                }
+            }
+            film.merge(&tile);
+        }
+
+        let image = film.resolve();
+        // Weighted 0.75 in its own pixel & 0.25 in the right one, against weights of 1 for the others
+        assert_eq!(0.0, image.get(0, 0).x);
+        assert!((image.get(1, 0).x - 1.0).abs() < 1e-12);
+        assert!((image.get(2, 0).x - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    pub fn non_finite_samples_are_dropped() {
+        let mut film = Film::new(2, 1, Filter::default());
+        for mut tile in film.tiles(2, &[2; 2]) {
+            tile.add_sample(
+                0,
+                0,
+                (0.5, 0.5),
+                Color::new(1.0, 1.0, 1.0),
+                &AovSample::default(),
+            );
+            tile.add_sample(
+                0,
+                0,
+                (0.5, 0.5),
+                Color::new(f64::NAN, 0.0, 0.0),
+                &AovSample::default(),
+            );
+            tile.add_sample(
+                1,
+                0,
+                (0.5, 0.5),
+                Color::new(0.5, 0.5, 0.5),
+                &AovSample::default(),
+            );
+            tile.add_sample(
+                1,
+                0,
+                (0.5, 0.5),
+                Color::new(0.5, f64::INFINITY, 0.5),
+                &AovSample::default(),
+            );
+            film.merge(&tile);
+        }
+
+        assert_eq!(vec![(0, 0, 1), (1, 0, 1)], film.dropped_samples());
+        assert_eq!(Color::new(1.0, 1.0, 1.0), film.resolve().get(0, 0));
+        assert_eq!(Color::new(0.5, 0.5, 0.5), film.resolve().get(1, 0));
+        // The dropped samples' indices aren't taken again
+        let tiles = film.tiles(2, &[1; 2]);
+        assert!(tiles[0]
+            .pixels()
+            .iter()
+            .all(|(_, _, samples)| samples.start == 2));
+    }
+
+    #[test]
+    pub fn size_checks_every_buffer() {
+        let mut film = Film::with_aovs(3, 2, Filter::default());
+        assert!(film.has_size(3, 2));
+        assert!(!film.has_size(2, 3));
+
+        film.aovs.as_mut().unwrap().depth.pop();
+        assert!(!film.has_size(3, 2));
+    }
+}
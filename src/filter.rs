@@ -0,0 +1,151 @@
+use std::f64::consts::PI;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+// Falloff of the gaussian, the same as pbrt's default
+const GAUSSIAN_ALPHA: f64 = 2.0;
+// Mitchell & Netravali's recommended B & C
+const MITCHELL_B: f64 = 1.0 / 3.0;
+const MITCHELL_C: f64 = 1.0 / 3.0;
+
+// Pixel reconstruction filters, weighting how much each sample counts towards the pixels
+// around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    // The radius in pixels used when none is given
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    kind: FilterKind,
+    radius: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: Option<f64>) -> Self {
+        Self {
+            kind,
+            radius: radius.unwrap_or(kind.default_radius()).max(0.5),
+        }
+    }
+
+    #[inline]
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    // How many pixels a sample can reach past the one it was taken in
+    pub fn margin(&self) -> usize {
+        (self.radius - 0.5).ceil().max(0.0) as usize
+    }
+
+    // The weight of a sample offset by (dx, dy) pixels from a pixel's center.
+    // Mitchell & Lanczos have negative lobes, which sharpen the image
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / self.radius,
+            FilterKind::Gaussian => {
+                (-GAUSSIAN_ALPHA * x * x).exp() - (-GAUSSIAN_ALPHA * self.radius.powi(2)).exp()
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / self.radius),
+            FilterKind::Lanczos => sinc(x) * sinc(x / self.radius),
+        }
+    }
+}
+
+// A box filter over each pixel, which averages the samples taken in it
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box, None)
+    }
+}
+
+// Mitchell-Netravali cubic over [0, 2)
+fn mitchell(x: f64) -> f64 {
+    let (b, c) = (MITCHELL_B, MITCHELL_C);
+    let weight = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+            + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2)
+            + (6.0 - 2.0 * b)
+    } else {
+        (-b - 6.0 * c) * x.powi(3)
+            + (6.0 * b + 30.0 * c) * x.powi(2)
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)
+    };
+
+    weight / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, FilterKind};
+
+    #[test]
+    pub fn filters_peak_at_the_center_and_vanish_past_the_radius() {
+        for kind in [
+            FilterKind::Box,
+            FilterKind::Tent,
+            FilterKind::Gaussian,
+            FilterKind::Mitchell,
+            FilterKind::Lanczos,
+        ] {
+            let filter = Filter::new(kind, None);
+            let center = filter.weight(0.0, 0.0);
+
+            assert!(center > 0.0, "{kind:?}");
+            assert_eq!(0.0, filter.weight(filter.radius() + 0.01, 0.0), "{kind:?}");
+            assert!(
+                (0..100).all(|i| {
+                    let x = i as f64 / 100.0 * filter.radius();
+                    filter.weight(x, 0.0) <= center
+                        && filter.weight(x, 0.0) == filter.weight(-x, 0.0)
+                }),
+                "{kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn mitchell_has_negative_lobes() {
+        let filter = Filter::new(FilterKind::Mitchell, None);
+
+        assert!(filter.weight(1.5, 0.0) < 0.0);
+    }
+}
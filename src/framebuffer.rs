@@ -0,0 +1,40 @@
+use crate::math::Color;
+
+// A linear radiance image stored row-major, top row first
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl FrameBuffer {
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(width * height, pixels.len());
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
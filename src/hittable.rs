@@ -1,10 +1,39 @@
 use std::sync::Arc;
 
-use crate::{aabb::AABB, material::Material, math::Vector, ray::Ray, Point};
+use crate::{
+    aabb::AABB,
+    material::Material,
+    math::{Color, RenderRng, Vector},
+    ray::Ray,
+    sampler::Sampler,
+    scene_file::{ObjectDescription, SceneExporter},
+    Point,
+};
 
 pub trait Hittable: Sync + Send {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord>;
     fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<AABB>;
+    // Add the materials of everything inside, for numbering them
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>);
+
+    // The density, over solid angle, of sample_direction picking direction from origin
+    fn pdf_value(
+        &self,
+        _origin: Point,
+        _direction: Vector,
+        _time: f64,
+        _rng: &mut RenderRng,
+    ) -> f64 {
+        0.0
+    }
+
+    // Pick a direction from origin towards the surface, for sampling it as a light
+    fn sample_direction(&self, _origin: Point, _time: f64, _sampler: &mut dyn Sampler) -> Vector {
+        Vector::new(1.0, 0.0, 0.0)
+    }
+
+    // How the object is written in a scene file
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription;
 }
 
 pub struct HittableList {
@@ -20,18 +49,27 @@ impl HittableList {
         self.objects.push(hittable)
     }
 
-    pub fn as_raw(self) -> Vec<Arc<dyn Hittable>> {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn into_raw(self) -> Vec<Arc<dyn Hittable>> {
         self.objects
     }
+
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
         let mut hit_record = None;
         let mut closest_so_far = t_max;
 
         for object in self.objects.iter() {
-            if let Some(record) = object.hit(ray, t_min, closest_so_far) {
+            if let Some(record) = object.hit(ray, t_min, closest_so_far, rng) {
                 closest_so_far = record.t;
                 hit_record = Some(record);
             }
@@ -62,6 +100,40 @@ impl Hittable for HittableList {
 
         outer_box
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        for object in self.objects.iter() {
+            object.materials(materials);
+        }
+    }
+
+    // Each object is picked with equal probability
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction, time, rng))
+            .sum();
+
+        sum / self.objects.len() as f64
+    }
+
+    fn sample_direction(&self, origin: Point, time: f64, sampler: &mut dyn Sampler) -> Vector {
+        let index = (sampler.get_1d() * self.objects.len() as f64) as usize;
+
+        self.objects[index.min(self.objects.len() - 1)].sample_direction(origin, time, sampler)
+    }
+
+    // Scene files only group objects under a BVH, which is hit the same as the list
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::Bvh {
+            objects: self
+                .objects
+                .iter()
+                .map(|object| object.describe(exporter))
+                .collect(),
+        }
+    }
 }
 
 pub struct HitRecord {
@@ -72,6 +144,10 @@ pub struct HitRecord {
     pub normal: Vector,
     pub material: Arc<dyn Material>,
     pub face: Face,
+    // Which of the scene's top level objects was hit, zero until it's known
+    pub object_id: u32,
+    // Interpolated from the vertices of meshes which have colors
+    pub vertex_color: Option<Color>,
 }
 
 impl HitRecord {
@@ -92,6 +168,8 @@ impl HitRecord {
                 normal: -outward_normal,
                 material,
                 face: Face::Back,
+                object_id: 0,
+                vertex_color: None,
             }
         } else {
             HitRecord {
@@ -102,6 +180,8 @@ impl HitRecord {
                 normal: outward_normal,
                 material,
                 face: Face::Front,
+                object_id: 0,
+                vertex_color: None,
             }
         }
     }
@@ -1,8 +1,13 @@
+use std::sync::Arc;
+
 use crate::{
     aabb::AABB,
     hittable::{HitRecord, Hittable},
-    math::{Point, Rotation, Vector},
+    material::Material,
+    math::{Point, RenderRng, Rotation, Vector},
     ray::Ray,
+    sampler::Sampler,
+    scene_file::{ObjectDescription, SceneExporter},
 };
 
 pub struct Translate {
@@ -17,9 +22,9 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
         let offset_ray = Ray::new(ray.origin() - self.offset, ray.direction(), ray.time());
-        match self.hittable.hit(&offset_ray, t_min, t_max) {
+        match self.hittable.hit(&offset_ray, t_min, t_max, rng) {
             Some(mut hit) => {
                 hit.p += self.offset;
 
@@ -30,12 +35,29 @@ impl Hittable for Translate {
     }
 
     fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<crate::aabb::AABB> {
-        match self.hittable.bounding_box(start_time, end_time) {
-            Some(aabb) => Some(AABB::new(
-                aabb.minimum() + self.offset,
-                aabb.maximum() + self.offset,
-            )),
-            None => None,
+        self.hittable
+            .bounding_box(start_time, end_time)
+            .map(|aabb| AABB::new(aabb.minimum() + self.offset, aabb.maximum() + self.offset))
+    }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        self.hittable.materials(materials);
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        self.hittable
+            .pdf_value(origin - self.offset, direction, time, rng)
+    }
+
+    fn sample_direction(&self, origin: Point, time: f64, sampler: &mut dyn Sampler) -> Vector {
+        self.hittable
+            .sample_direction(origin - self.offset, time, sampler)
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::Translate {
+            offset: self.offset,
+            object: Box::new(self.hittable.describe(exporter)),
         }
     }
 }
@@ -44,6 +66,8 @@ pub struct Rotate {
     hittable: Box<dyn Hittable>,
     rotation: Rotation,
     aabb: Option<AABB>,
+    // In degrees, for writing it out
+    angle: f64,
 }
 
 impl Rotate {
@@ -87,19 +111,21 @@ impl Rotate {
                     hittable,
                     rotation: Rotation::from_euler_angles(0.0, rads, 0.0),
                     aabb: Some(AABB::new(min, max)),
+                    angle: gamma,
                 }
             }
             None => Self {
                 hittable,
                 rotation: Rotation::from_euler_angles(0.0, rads, 0.0),
                 aabb: None,
+                angle: gamma,
             },
         }
     }
 }
 
 impl Hittable for Rotate {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
         // Rotate the ray opposite to the transform
         let rotated_ray = Ray::new(
             self.rotation.inverse_transform_point(&ray.origin()),
@@ -107,7 +133,7 @@ impl Hittable for Rotate {
             ray.time(),
         );
 
-        match self.hittable.hit(&rotated_ray, t_min, t_max) {
+        match self.hittable.hit(&rotated_ray, t_min, t_max, rng) {
             Some(mut hit) => {
                 // Rotate the hit in the direction of the transform
                 hit.p = self.rotation.transform_point(&hit.p);
@@ -122,4 +148,68 @@ impl Hittable for Rotate {
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
         self.aabb.clone()
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        self.hittable.materials(materials);
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        self.hittable.pdf_value(
+            self.rotation.inverse_transform_point(&origin),
+            self.rotation.inverse_transform_vector(&direction),
+            time,
+            rng,
+        )
+    }
+
+    fn sample_direction(&self, origin: Point, time: f64, sampler: &mut dyn Sampler) -> Vector {
+        let direction = self.hittable.sample_direction(
+            self.rotation.inverse_transform_point(&origin),
+            time,
+            sampler,
+        );
+
+        self.rotation.transform_vector(&direction)
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::Rotate {
+            angle: self.angle,
+            object: Box::new(self.hittable.describe(exporter)),
+        }
+    }
+}
+
+// Labels hits with the id of one of the scene's top level objects
+pub struct Identified {
+    hittable: Arc<dyn Hittable>,
+    id: u32,
+}
+
+impl Identified {
+    pub fn new(hittable: Arc<dyn Hittable>, id: u32) -> Self {
+        Self { hittable, id }
+    }
+}
+
+impl Hittable for Identified {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        self.hittable.hit(ray, t_min, t_max, rng).map(|mut hit| {
+            hit.object_id = self.id;
+
+            hit
+        })
+    }
+
+    fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<AABB> {
+        self.hittable.bounding_box(start_time, end_time)
+    }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        self.hittable.materials(materials);
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        self.hittable.describe(exporter)
+    }
 }
@@ -0,0 +1,527 @@
+use std::{collections::HashMap, sync::Arc};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    film::AovSample,
+    hittable::{Face, HitRecord, Hittable, HittableList},
+    material::{material_key, Material, Scatter},
+    math::Color,
+    pdf::{HittablePdf, MixturePdf, Pdf},
+    ray::Ray,
+    sampler::Sampler,
+    stats,
+    tonemap::{heat_color, luminance},
+    MIN_INTERSECTION_DISTANCE,
+};
+
+// BVH node visits shown as white by the heatmap
+const HEATMAP_MAX_VISITS: f64 = 128.0;
+
+// Works out how much light arrives at the camera along a ray
+pub trait Integrator: Sync + Send {
+    // Also fills in what the integrator knows of aovs
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum IntegratorKind {
+    // Path tracing, the only integrator which gives a real image
+    Path,
+    // Shading normal at the first hit, mapped from [-1, 1] to [0, 1]
+    Normal,
+    // Distance to the first hit, in scene units
+    Depth,
+    // Surface color at the first hit, or emitted light for lights
+    Albedo,
+    // Texture coordinates at the first hit in red & green
+    Uv,
+    // Green where the first hit is on the front of a surface, red where it's on the back
+    Face,
+    // How many BVH nodes the camera ray visited, from black through red & yellow to white
+    BvhHeatmap,
+}
+
+impl IntegratorKind {
+    pub fn build(
+        &self,
+        min_depth: i64,
+        max_depth: i64,
+        light_sampling: LightSampling,
+        clamp: LuminanceClamp,
+    ) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::Path => Box::new(PathIntegrator {
+                min_depth,
+                max_depth,
+                light_sampling,
+                clamp,
+            }),
+            IntegratorKind::Normal => Box::new(NormalIntegrator),
+            IntegratorKind::Depth => Box::new(DepthIntegrator),
+            IntegratorKind::Albedo => Box::new(AlbedoIntegrator),
+            IntegratorKind::Uv => Box::new(UvIntegrator),
+            IntegratorKind::Face => Box::new(FaceIntegrator),
+            IntegratorKind::BvhHeatmap => Box::new(BvhHeatmapIntegrator),
+        }
+    }
+}
+
+// The most luminance a single sample's direct & indirect light may have, anything brighter
+// is scaled down. Loses some energy, but stops rare bright paths turning into fireflies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LuminanceClamp {
+    pub direct: Option<f64>,
+    pub indirect: Option<f64>,
+}
+
+fn clamp_luminance(color: Color, max: Option<f64>) -> Color {
+    match max {
+        Some(max) if luminance(color) > max => color * max / luminance(color),
+        _ => color,
+    }
+}
+
+// Follows rays for up to max_depth hits.
+// After min_depth hits, paths carrying little light are ended early by Russian roulette
+pub struct PathIntegrator {
+    min_depth: i64,
+    max_depth: i64,
+    light_sampling: LightSampling,
+    clamp: LuminanceClamp,
+}
+
+impl Integrator for PathIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color {
+        let lights = environment.lights;
+
+        // Light reaching the camera after no bounces, one bounce & more than one bounce
+        let mut light = [Color::new(0.0, 0.0, 0.0); 3];
+        // How much of the light arriving along the current ray reaches the camera
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = ray.clone();
+        // The density the previous hit scattered the ray with when it also sampled the lights,
+        // so light the ray finds has to be weighted against that
+        let mut scattering_pdf = None;
+
+        for depth in 0..self.max_depth {
+            let bounces = (depth as usize).min(2);
+            let hit = match environment.trace(&ray, sampler) {
+                Some(hit) => hit,
+                None => {
+                    light[bounces] += throughput.component_mul(&environment.background);
+                    break;
+                }
+            };
+            stats::count_path_vertex();
+            if depth == 0 {
+                record_first_hit(&ray, &hit, environment, aovs);
+            }
+
+            let mut emitted = hit.material.emitted(&hit);
+            if let Some(scattering_pdf) = scattering_pdf {
+                if emitted != Color::new(0.0, 0.0, 0.0) {
+                    let light_pdf = stats::uncounted(|| {
+                        lights.pdf_value(ray.origin(), ray.direction(), ray.time(), sampler.rng())
+                    });
+                    emitted *= power_heuristic(scattering_pdf, light_pdf);
+                }
+            }
+            light[bounces] += throughput.component_mul(&emitted);
+
+            let scatter = match hit.material.scatter(&ray, &hit, sampler) {
+                Some(scatter) => scatter,
+                None => {
+                    if depth == 0 {
+                        aovs.albedo = emitted;
+                    }
+                    break;
+                }
+            };
+            if depth == 0 {
+                aovs.albedo = scatter.attentuation;
+            }
+
+            match scatter.scatter {
+                // Mirror like scattering can only find lights by following the ray
+                Scatter::Specular(scattered) => {
+                    throughput.component_mul_assign(&scatter.attentuation);
+                    ray = scattered;
+                    scattering_pdf = None;
+                }
+                Scatter::Pdf(material_pdf) => {
+                    let light_sampling = match self.light_sampling {
+                        _ if lights.is_empty() => LightSampling::Off,
+                        light_sampling => light_sampling,
+                    };
+                    let light_pdf = HittablePdf::new(lights, hit.p, ray.time());
+                    let mixture_pdf = MixturePdf::new(&light_pdf, material_pdf.as_ref(), 0.5);
+                    let pdf: &dyn Pdf = match light_sampling {
+                        LightSampling::Mis => {
+                            let direct = sample_light(
+                                &ray,
+                                &hit,
+                                scatter.attentuation,
+                                material_pdf.as_ref(),
+                                environment,
+                                sampler,
+                            );
+                            light[(bounces + 1).min(2)] += throughput.component_mul(&direct);
+
+                            material_pdf.as_ref()
+                        }
+                        // Taking the direction from the mixture weights it against both
+                        // strategies already
+                        LightSampling::Mixture => &mixture_pdf,
+                        LightSampling::Off => material_pdf.as_ref(),
+                    };
+
+                    let scattered = Ray::new(hit.p, pdf.generate(sampler), ray.time());
+                    let pdf_value = pdf.value(&scattered.direction(), sampler.rng());
+                    if pdf_value <= 0.0 {
+                        break;
+                    }
+
+                    let weight = scatter.attentuation
+                        * hit.material.scattering_pdf(&ray, &hit, &scattered)
+                        / pdf_value;
+                    throughput.component_mul_assign(&weight);
+                    ray = scattered;
+                    scattering_pdf = (light_sampling == LightSampling::Mis).then_some(pdf_value);
+                }
+            }
+
+            // Carry on with a chance matching the light left in the path, boosting the paths
+            // which survive to make up for the ones which don't
+            if depth + 1 >= self.min_depth {
+                let survival = throughput.max().min(0.95);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        // Light seen straight from the camera is left alone, it can't cause fireflies
+        light[1] = clamp_luminance(light[1], self.clamp.direct);
+        light[2] = clamp_luminance(light[2], self.clamp.indirect);
+
+        aovs.emission = light[0];
+        aovs.direct = light[1];
+        aovs.indirect = light[2];
+
+        light[0] + light[1] + light[2]
+    }
+}
+
+// Light arriving directly from a sampled point on one of the lights
+fn sample_light(
+    ray: &Ray,
+    hit: &HitRecord,
+    attenuation: Color,
+    material_pdf: &dyn Pdf,
+    environment: &Environment,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let light_pdf = HittablePdf::new(environment.lights, hit.p, ray.time());
+    let shadow_ray = Ray::new(hit.p, light_pdf.generate(sampler), ray.time());
+
+    let light_pdf_value = light_pdf.value(&shadow_ray.direction(), sampler.rng());
+    let scattering_pdf = hit.material.scattering_pdf(ray, hit, &shadow_ray);
+    if light_pdf_value <= 0.0 || scattering_pdf <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    // Whatever the shadow ray hits first is the light that arrives
+    let emitted = match environment.trace(&shadow_ray, sampler) {
+        Some(light_hit) => light_hit.material.emitted(&light_hit),
+        None => return Color::new(0.0, 0.0, 0.0),
+    };
+
+    let material_pdf_value = material_pdf.value(&shadow_ray.direction(), sampler.rng());
+    attenuation.component_mul(&emitted)
+        * scattering_pdf
+        * power_heuristic(light_pdf_value, material_pdf_value)
+        / light_pdf_value
+}
+
+fn record_first_hit(ray: &Ray, hit: &HitRecord, environment: &Environment, aovs: &mut AovSample) {
+    aovs.normal = hit.normal;
+    aovs.depth = hit.t * ray.direction().magnitude();
+    aovs.object_id = hit.object_id;
+    aovs.material_id = environment.material_id(&hit.material);
+}
+
+// The first thing the camera ray hits, for the debug integrators
+fn first_hit(
+    ray: &Ray,
+    environment: &Environment,
+    sampler: &mut dyn Sampler,
+    aovs: &mut AovSample,
+) -> Option<HitRecord> {
+    let hit = environment.trace(ray, sampler)?;
+    stats::count_path_vertex();
+    record_first_hit(ray, &hit, environment, aovs);
+
+    Some(hit)
+}
+
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color {
+        match first_hit(ray, environment, sampler, aovs) {
+            Some(hit) => (hit.normal.normalize() + Color::new(1.0, 1.0, 1.0)) / 2.0,
+            None => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+pub struct DepthIntegrator;
+
+impl Integrator for DepthIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color {
+        match first_hit(ray, environment, sampler, aovs) {
+            Some(hit) => {
+                let distance = hit.t * ray.direction().magnitude();
+
+                Color::new(distance, distance, distance)
+            }
+            None => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+pub struct AlbedoIntegrator;
+
+impl Integrator for AlbedoIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color {
+        match first_hit(ray, environment, sampler, aovs) {
+            Some(hit) => match hit.material.scatter(ray, &hit, sampler) {
+                Some(scatter) => scatter.attentuation,
+                None => hit.material.emitted(&hit),
+            },
+            None => environment.background,
+        }
+    }
+}
+
+pub struct UvIntegrator;
+
+impl Integrator for UvIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color {
+        match first_hit(ray, environment, sampler, aovs) {
+            Some(hit) => Color::new(hit.u, hit.v, 0.0),
+            None => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+pub struct FaceIntegrator;
+
+impl Integrator for FaceIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color {
+        match first_hit(ray, environment, sampler, aovs).map(|hit| hit.face) {
+            Some(Face::Front) => Color::new(0.0, 1.0, 0.0),
+            Some(Face::Back) => Color::new(1.0, 0.0, 0.0),
+            None => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+pub struct BvhHeatmapIntegrator;
+
+impl Integrator for BvhHeatmapIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        environment: &Environment,
+        sampler: &mut dyn Sampler,
+        aovs: &mut AovSample,
+    ) -> Color {
+        let start = stats::counts().aabb_tests;
+        first_hit(ray, environment, sampler, aovs);
+        let visits = stats::counts().aabb_tests - start;
+
+        heat_color(visits as f64 / HEATMAP_MAX_VISITS)
+    }
+}
+
+// How lights are found at diffuse hits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum LightSampling {
+    // Sample a light & the material separately, combining both with multiple importance sampling
+    Mis,
+    // Scatter towards a light or by the material at random, as one sample
+    Mixture,
+    // Only find lights by scattering into them
+    Off,
+}
+
+// Everything about the scene a ray needs to gather light
+pub struct Environment<'a> {
+    pub background: Color,
+    pub world: &'a HittableList,
+    // Emitters which can be sampled directly, each should also be in world
+    pub lights: &'a HittableList,
+    // Numbers for each material, keyed by material_key
+    pub material_ids: &'a HashMap<usize, u32>,
+}
+
+impl Environment<'_> {
+    // The closest hit along the ray, counted for the render's stats
+    #[inline]
+    pub fn trace(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        stats::count_ray();
+        self.world
+            .hit(ray, MIN_INTERSECTION_DISTANCE, f64::MAX, sampler.rng())
+    }
+
+    #[inline]
+    pub fn material_id(&self, material: &Arc<dyn Material>) -> u32 {
+        self.material_ids
+            .get(&material_key(material))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+// Weight for a sample taken with density pdf, when other_pdf could also have taken it
+#[inline]
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    pdf.powi(2) / (pdf.powi(2) + other_pdf.powi(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use rand::SeedableRng;
+
+    use crate::{
+        bvh::BVHNode,
+        film::AovSample,
+        hittable::HittableList,
+        material::Lambertian,
+        math::{Color, Point, RenderRng, Vector},
+        ray::Ray,
+        sampler::SamplerKind,
+        sphere::Sphere,
+        tonemap::luminance,
+    };
+
+    use super::{clamp_luminance, Environment, IntegratorKind, LightSampling};
+
+    fn first_hit_view(kind: IntegratorKind, origin: Point) -> Color {
+        let mut objects = HittableList::new();
+        objects.add(Arc::new(Sphere::new(
+            Point::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new_from_color(Color::new(0.2, 0.4, 0.6))),
+        )));
+        let mut world = HittableList::new();
+        world.add(Arc::new(
+            BVHNode::new(objects, 0.0, 1.0, &mut RenderRng::seed_from_u64(0)).unwrap(),
+        ));
+        let environment = Environment {
+            background: Color::new(0.0, 0.0, 0.0),
+            world: &world,
+            lights: &HittableList::new(),
+            material_ids: &HashMap::new(),
+        };
+
+        let mut sampler = SamplerKind::Independent.build(0, 1);
+        sampler.start_sample(0, 0, 0);
+        kind.build(1, 1, LightSampling::Off, Default::default())
+            .radiance(
+                &Ray::new(origin, Vector::new(0.0, 0.0, -2.0), 0.0),
+                &environment,
+                sampler.as_mut(),
+                &mut AovSample::default(),
+            )
+    }
+
+    #[test]
+    pub fn debug_views_show_the_first_hit() {
+        let outside = Point::new(0.0, 0.0, 3.0);
+
+        assert_eq!(
+            Color::new(0.5, 0.5, 1.0),
+            first_hit_view(IntegratorKind::Normal, outside)
+        );
+        assert_eq!(
+            Color::new(2.0, 2.0, 2.0),
+            first_hit_view(IntegratorKind::Depth, outside)
+        );
+        assert_eq!(
+            Color::new(0.2, 0.4, 0.6),
+            first_hit_view(IntegratorKind::Albedo, outside)
+        );
+        assert_eq!(
+            Color::new(0.0, 1.0, 0.0),
+            first_hit_view(IntegratorKind::Face, outside)
+        );
+        assert_eq!(
+            Color::new(1.0, 0.0, 0.0),
+            first_hit_view(IntegratorKind::Face, Point::new(0.0, 0.0, 0.0))
+        );
+        assert_ne!(
+            Color::new(0.0, 0.0, 0.0),
+            first_hit_view(IntegratorKind::BvhHeatmap, outside)
+        );
+    }
+
+    #[test]
+    pub fn clamping_keeps_the_hue() {
+        let bright = Color::new(40.0, 20.0, 10.0);
+        let clamped = clamp_luminance(bright, Some(2.0));
+
+        assert!((luminance(clamped) - 2.0).abs() < 1e-12);
+        assert!((clamped.x / clamped.y - 2.0).abs() < 1e-12);
+        assert_eq!(bright, clamp_luminance(bright, None));
+        assert_eq!(bright, clamp_luminance(bright, Some(100.0)));
+    }
+}
@@ -1,106 +1,304 @@
 mod aabb;
 mod bvh;
 mod camera;
+mod checkpoint;
+mod cli;
+mod denoise;
+mod film;
+mod filter;
+mod framebuffer;
 mod hittable;
 mod instance;
+mod integrator;
 mod material;
 mod math;
+mod obj;
+mod output;
+mod pdf;
 mod perlin;
+mod ply;
 mod ray;
 mod rectangle;
+mod render;
+mod sampler;
 mod scene;
+mod scene_file;
 mod sphere;
+mod stats;
 mod texture;
+mod tonemap;
+mod triangle;
 mod volumes;
 
-use std::sync::Arc;
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    process,
+    time::{Duration, Instant},
+};
 
-use crate::{bvh::BVHNode, hittable::HittableList, math::Color, math::Point};
+use crate::{
+    checkpoint::CheckpointSettings,
+    cli::Cli,
+    film::Film,
+    math::Point,
+    output::OutputSettings,
+    render::Renderer,
+    tonemap::{DisplayTransform, TransferFunction},
+};
 
-use rayon::prelude::*;
+use clap::Parser;
 
 // Quick hack to avoid floating point uncertainty causing self intersections
 const MIN_INTERSECTION_DISTANCE: f64 = 0.0001;
 
-const THREADS: u64 = 16;
-const MAX_DEPTH: i64 = 50;
-
 const ASPECT_RATIO: f64 = 16.0 / 9.0;
 
+// Samples per pixel in each pass when a render is split into passes without --progressive
+const DEFAULT_PASS_SAMPLES: u64 = 16;
+
+// Pixels listed by name when warning about dropped samples
+const MAX_REPORTED_PIXELS: usize = 10;
+
 fn main() {
+    let cli = Cli::parse();
+
+    if cli.list_scenes {
+        list_scenes();
+        return;
+    }
+
+    let result = match &cli.export_scene {
+        Some(path) => export_scene(&cli, path),
+        None => render(&cli),
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {err}");
+        process::exit(1);
+    }
+}
+
+fn list_scenes() {
+    for entry in scene::SCENES {
+        let params: Vec<String> = entry
+            .params
+            .iter()
+            .map(|(name, default)| format!(" {name}={default}"))
+            .collect();
+
+        println!("{}{}", entry.name, params.concat());
+    }
+}
+
+// The scene as it's built, the image size & samples aren't overridden
+fn export_scene(cli: &Cli, path: &Path) -> Result<(), Box<dyn Error>> {
+    let scene = scene::build_scene(&cli.scene, &cli.params, cli.seed)?;
+    scene_file::export(&scene, path)?;
+    eprintln!("Wrote {}", path.display());
+
+    Ok(())
+}
+
+fn render(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let checkpoint = cli.resume.as_deref().map(checkpoint::load).transpose()?;
+
     // Scene
-    let scene = scene::week_scene(20, 1000);
-    let samples_per_pixel_per_thread = scene.image.samples_per_pixel / THREADS;
+    let (scene_name, params, seed) = match &checkpoint {
+        Some(checkpoint) => (
+            &checkpoint.settings.scene,
+            &checkpoint.settings.params,
+            checkpoint.settings.seed,
+        ),
+        None => (&cli.scene, &cli.params, cli.seed),
+    };
+    let mut scene = scene::build_scene(scene_name, params, seed)?;
+    let mut render_settings = cli.render_settings();
+    render_settings.seed = seed;
+    match &checkpoint {
+        Some(checkpoint) => {
+            scene.image.resize(
+                Some(checkpoint.settings.width),
+                Some(checkpoint.settings.height),
+            );
+            render_settings.min_depth = checkpoint.settings.min_depth;
+            render_settings.max_depth = checkpoint.settings.max_depth;
+            render_settings.sampler = checkpoint.settings.sampler;
+            render_settings.integrator = checkpoint.settings.integrator;
+            render_settings.light_sampling = checkpoint.settings.light_sampling;
+            render_settings.clamp = checkpoint.settings.clamp;
+        }
+        None => scene.image.resize(cli.width, cli.height),
+    }
+    if let Some(samples) = cli.samples {
+        scene.image.samples_per_pixel = samples;
+    }
+    render_settings.samples_per_pixel = scene.image.samples_per_pixel;
 
-    // BVH
-    let bvh = BVHNode::new(scene.objects, 0.0, 1.0);
-    let mut world = HittableList::new();
-    world.add(Arc::new(bvh));
+    let checkpoint_settings = CheckpointSettings {
+        scene: scene_name.clone(),
+        params: params.clone(),
+        width: scene.image.width,
+        height: scene.image.height,
+        min_depth: render_settings.min_depth,
+        max_depth: render_settings.max_depth,
+        seed,
+        sampler: render_settings.sampler,
+        integrator: render_settings.integrator,
+        light_sampling: render_settings.light_sampling,
+        clamp: render_settings.clamp,
+    };
+
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+
+    let samples_per_pixel = scene.image.samples_per_pixel;
+    let renderer = Renderer::new(scene, render_settings);
+
+    let output_settings = cli.output_settings();
+    let snapshot_path = match cli.progressive {
+        Some(_) => Some(
+            cli.output
+                .as_deref()
+                .ok_or("progressive rendering needs an --output file for its snapshots")?,
+        ),
+        None => None,
+    };
+    let aov_path = match cli.aov.is_empty() {
+        true => None,
+        false => Some(
+            cli.output
+                .as_deref()
+                .ok_or("AOVs need an --output file to be written next to")?,
+        ),
+    };
+    let denoise_settings = cli.denoise_settings();
+    // The denoiser is guided by the AOVs, so they're rendered even if not written
+    let film_aovs = aov_path.is_some() || denoise_settings.is_some();
+    let adaptive_settings = cli.adaptive_settings(samples_per_pixel);
+    let in_passes =
+        cli.checkpoint.is_some() || adaptive_settings.is_some() || cli.time_limit.is_some();
+    let pass_samples = match cli.progressive {
+        Some(pass_samples) => pass_samples,
+        None if in_passes => DEFAULT_PASS_SAMPLES,
+        None => samples_per_pixel,
+    };
+
+    let snapshot_interval = Duration::from_secs_f64(cli.snapshot_interval.unwrap_or(0.0));
+    let checkpoint_interval = Duration::from_secs_f64(cli.checkpoint_interval);
+    let mut last_snapshot = Instant::now();
+    let mut last_checkpoint = Instant::now();
 
     // Render
-    println!("P3");
-    println!("{} {}", scene.image.width, scene.image.height);
-    println!("255");
-
-    let images: Vec<Vec<Color>> = (0..THREADS)
-        .into_par_iter()
-        .map(|t| {
-            let mut image = vec![];
-
-            for j in (0..scene.image.height).rev() {
-                if j % 10 == 0 {
-                    eprintln!("Thread {t}, scanlines remaining: {j}");
-                }
-                for i in 0..scene.image.width {
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                    for _ in 0..samples_per_pixel_per_thread {
-                        let u = (i as f64 + rand::random::<f64>()) / (scene.image.width - 1) as f64;
-                        let v =
-                            (j as f64 + rand::random::<f64>()) / (scene.image.height - 1) as f64;
-                        let ray = scene.camera.get_ray(u, v);
-                        pixel_color += ray.color(scene.background, &world, MAX_DEPTH);
-                    }
-
-                    image.push(average_pixel(pixel_color, samples_per_pixel_per_thread))
-                }
+    let render_start = Instant::now();
+    let (mut film, rendered) = match checkpoint {
+        Some(checkpoint) => {
+            eprintln!(
+                "Resuming from {} samples per pixel",
+                checkpoint.samples_per_pixel
+            );
+            if film_aovs && !checkpoint.film.has_aovs() {
+                return Err("the checkpoint was rendered without AOVs".into());
             }
+            (checkpoint.film, checkpoint.samples_per_pixel)
+        }
+        None => (renderer.film(film_aovs), 0),
+    };
 
-            image
-        })
-        .collect();
+    let after_pass = |film: &Film, rendered: u64, finished: bool| {
+        // The final image is always written below. Snapshots are only taken between passes, so
+        // the interval is a minimum gap rather than a schedule
+        if let Some(path) = snapshot_path {
+            if !finished && last_snapshot.elapsed() >= snapshot_interval {
+                output::replace_image(&film.resolve(), path, &output_settings)?;
+                last_snapshot = Instant::now();
+            }
+        }
 
-    for i in 0..images[0].len() {
-        // Add up all the values for each pixel in each image
-        let color = images
-            .iter()
-            .fold(Color::new(0.0, 0.0, 0.0), |acc, image| acc + image[i]);
+        if let Some(path) = &cli.checkpoint {
+            if finished || last_checkpoint.elapsed() >= checkpoint_interval {
+                checkpoint::save(path, &checkpoint_settings, rendered, film)?;
+                last_checkpoint = Instant::now();
+            }
+        }
 
-        write_pixel(average_pixel(color, THREADS));
+        Ok::<(), Box<dyn Error>>(())
+    };
+    match &adaptive_settings {
+        Some(adaptive) => {
+            renderer.render_adaptive(&mut film, adaptive, pass_samples, after_pass)?
+        }
+        None => renderer.render_progressive(&mut film, rendered, pass_samples, after_pass)?,
+    }
+    let stats = renderer.stats(render_start.elapsed());
+    report_dropped_samples(&film);
+    let image = match &denoise_settings {
+        Some(denoise_settings) => denoise::denoise(&film, denoise_settings)
+            .ok_or("the film has no AOVs to guide the denoiser")?,
+        None => film.resolve(),
+    };
+
+    match aov_path {
+        Some(path) => {
+            let aovs: Vec<_> = cli
+                .aov
+                .iter()
+                .filter_map(|&aov| Some((aov, film.resolve_aov(aov)?)))
+                .collect();
+            output::write_with_aovs(&image, &aovs, path, cli.aov_files, &output_settings)?
+        }
+        None => output::write_image(&image, cli.output.as_deref(), &output_settings)?,
+    }
+
+    if let Some(path) = &cli.sample_heatmap {
+        let heatmap_settings = OutputSettings {
+            format: None,
+            display: DisplayTransform {
+                transfer: TransferFunction::Linear,
+                ..Default::default()
+            },
+            ..cli.output_settings()
+        };
+        output::write_image(&film.sample_heatmap(), Some(path), &heatmap_settings)?;
+    }
+
+    eprintln!("{stats}");
+    if let Some(path) = &cli.stats_json {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, &stats)?;
+        writeln!(out)?;
+        out.flush()?;
     }
 
     eprintln!("Done");
+
+    Ok(())
 }
 
-fn average_pixel(pixel_color: Color, samples_per_pixel: u64) -> Color {
-    let scale = 1.0 / samples_per_pixel as f64;
+fn report_dropped_samples(film: &Film) {
+    let dropped = film.dropped_samples();
+    if dropped.is_empty() {
+        return;
+    }
 
-    Color::new(
-        pixel_color.x * scale,
-        pixel_color.y * scale,
-        pixel_color.z * scale,
-    )
-}
+    let total: u64 = dropped.iter().map(|(_, _, count)| count).sum();
+    let pixels: Vec<String> = dropped
+        .iter()
+        .take(MAX_REPORTED_PIXELS)
+        .map(|(x, y, count)| format!("({x}, {y}) x{count}"))
+        .collect();
+    let more = match dropped.len().saturating_sub(MAX_REPORTED_PIXELS) {
+        0 => String::new(),
+        more => format!(" & {more} more"),
+    };
 
-fn write_pixel(pixel_color: Color) {
-    // Perform gamma correction
-    let r = pixel_color.x.sqrt();
-    let g = pixel_color.y.sqrt();
-    let b = pixel_color.z.sqrt();
-
-    println!(
-        "{} {} {}",
-        (256.0 * r.clamp(0.0, 0.999)) as u64,
-        (256.0 * g.clamp(0.0, 0.999)) as u64,
-        (256.0 * b.clamp(0.0, 0.999)) as u64
-    )
+    eprintln!(
+        "Warning: dropped {total} NaN or infinite samples in {} pixels: {}{more}",
+        dropped.len(),
+        pixels.join(", ")
+    );
 }
@@ -1,24 +1,53 @@
-use std::sync::Arc;
+use std::{f64::consts::PI, sync::Arc};
 
 use nalgebra::Unit;
 
 use crate::{
     hittable::{Face, HitRecord},
-    math::{near_zero, random_in_unit_sphere, random_unit_vector, Color, Point, Vector},
+    math::{sample_unit_ball, Color, Vector},
+    pdf::{CosinePdf, Pdf, SpherePdf},
     ray::Ray,
+    sampler::Sampler,
+    scene_file::{MaterialDescription, SceneExporter},
     texture::{SolidColorTexture, Texture},
 };
 
 pub trait Material: Sync + Send {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<ScatterRecord>;
-    fn emitted(&self, _u: f64, _v: f64, _p: Point) -> Color {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord>;
+    // How much of the light is sent along scattered, as a density over solid angle.
+    // Only used for materials which scatter with a Pdf
+    fn scattering_pdf(&self, _ray_in: &Ray, _hit: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+    fn emitted(&self, _hit: &HitRecord) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
+    // How the material is written in a scene file
+    fn describe(&self, exporter: &mut SceneExporter) -> MaterialDescription;
+}
+
+// Identifies a material by the allocation it's shared through
+#[inline]
+pub fn material_key(material: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(material) as *const () as usize
 }
 
 pub struct ScatterRecord {
-    pub ray: Ray,
     pub attentuation: Color,
+    pub scatter: Scatter,
+}
+
+pub enum Scatter {
+    // A single outgoing ray, like a mirror or glass
+    Specular(Ray),
+    // Directions from the hit point distributed by a density which can be evaluated, so
+    // they can be importance sampled & combined with light sampling
+    Pdf(Box<dyn Pdf>),
 }
 
 pub struct Lambertian {
@@ -37,19 +66,24 @@ impl Lambertian {
 
 impl Material for Lambertian {
     // A diffuse scatter that produces a lambertian distribution (Proportional to cos(phi))
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<ScatterRecord> {
-        let mut scatter_direction = hit.normal + random_unit_vector();
-
-        // Catch degenerate scatter direction
-        if near_zero(&scatter_direction) {
-            scatter_direction = hit.normal
-        }
-
+    fn scatter(&self, _: &Ray, hit: &HitRecord, _: &mut dyn Sampler) -> Option<ScatterRecord> {
         Some(ScatterRecord {
-            ray: Ray::new(hit.p, scatter_direction, ray_in.time()),
-            attentuation: self.albedo.value(hit.u, hit.v, hit.p),
+            attentuation: self.albedo.value(hit),
+            scatter: Scatter::Pdf(Box::new(CosinePdf::new(hit.normal))),
         })
     }
+
+    fn scattering_pdf(&self, _: &Ray, hit: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = hit.normal.dot(&scattered.direction().normalize());
+
+        cosine.max(0.0) / PI
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> MaterialDescription {
+        MaterialDescription::Lambertian {
+            albedo: exporter.texture(self.albedo.as_ref()),
+        }
+    }
 }
 
 pub struct Metal {
@@ -64,19 +98,31 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let reflected = reflect(&Unit::new_normalize(ray_in.direction()), &hit.normal);
 
         // TODO: Absorb rays which scatter inside the original object
         Some(ScatterRecord {
-            ray: Ray::new(
+            attentuation: self.albedo,
+            scatter: Scatter::Specular(Ray::new(
                 hit.p,
-                reflected + self.fuzz * random_in_unit_sphere(),
+                reflected + self.fuzz * sample_unit_ball(sampler.get_2d(), sampler.get_1d()),
                 ray_in.time(),
-            ),
-            attentuation: self.albedo,
+            )),
         })
     }
+
+    fn describe(&self, _: &mut SceneExporter) -> MaterialDescription {
+        MaterialDescription::Metal {
+            albedo: self.albedo,
+            fuzz: self.fuzz,
+        }
+    }
 }
 
 pub struct Dielectric {
@@ -97,7 +143,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let refraction_ratio = match hit.face {
             Face::Front => 1.0 / self.refraction_index,
             Face::Back => self.refraction_index,
@@ -107,8 +158,10 @@ impl Material for Dielectric {
         let cos_theta = -unit_direction.dot(&hit.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
+        // Always take the sample so the sampler's dimensions stay in step
+        let reflect_sample = sampler.get_1d();
         let direction = if refraction_ratio * sin_theta > 1.0
-            || self.reflectance(cos_theta, refraction_ratio) > rand::random()
+            || self.reflectance(cos_theta, refraction_ratio) > reflect_sample
         {
             reflect(&unit_direction, &hit.normal)
         } else {
@@ -116,10 +169,16 @@ impl Material for Dielectric {
         };
 
         Some(ScatterRecord {
-            ray: Ray::new(hit.p, direction, ray_in.time()),
             attentuation: Color::new(1.0, 1.0, 1.0),
+            scatter: Scatter::Specular(Ray::new(hit.p, direction, ray_in.time())),
         })
     }
+
+    fn describe(&self, _: &mut SceneExporter) -> MaterialDescription {
+        MaterialDescription::Dielectric {
+            refraction_index: self.refraction_index,
+        }
+    }
 }
 
 #[inline]
@@ -151,12 +210,18 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(&self, _: &Ray, _: &HitRecord, _: &mut dyn Sampler) -> Option<ScatterRecord> {
         None
     }
 
-    fn emitted(&self, u: f64, v: f64, p: Point) -> Color {
-        self.emit.value(u, v, p)
+    fn emitted(&self, hit: &HitRecord) -> Color {
+        self.emit.value(hit)
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> MaterialDescription {
+        MaterialDescription::DiffuseLight {
+            emit: exporter.texture(self.emit.as_ref()),
+        }
     }
 }
 
@@ -172,13 +237,27 @@ impl Isotropic {
     pub fn new_from_color(color: Color) -> Self {
         Self::new(Box::new(SolidColorTexture::new(color)))
     }
+
+    pub fn albedo(&self) -> &dyn Texture {
+        self.albedo.as_ref()
+    }
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(&self, _: &Ray, hit: &HitRecord, _: &mut dyn Sampler) -> Option<ScatterRecord> {
         Some(ScatterRecord {
-            ray: Ray::new(hit.p, random_in_unit_sphere(), ray_in.time()),
-            attentuation: self.albedo.value(hit.u, hit.v, hit.p),
+            attentuation: self.albedo.value(hit),
+            scatter: Scatter::Pdf(Box::new(SpherePdf)),
         })
     }
+
+    fn scattering_pdf(&self, _: &Ray, _: &HitRecord, _: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> MaterialDescription {
+        MaterialDescription::Isotropic {
+            albedo: exporter.texture(self.albedo.as_ref()),
+        }
+    }
 }
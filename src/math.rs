@@ -1,81 +1,140 @@
-use std::ops::Range;
+use std::{f64::consts::PI, ops::Range};
 
 use nalgebra::{Point3, Rotation3, Unit, Vector3};
-use rand::{thread_rng, Rng};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
 
 pub type Vector = Vector3<f64>;
 pub type Color = Vector3<f64>;
 pub type Point = Point3<f64>;
 pub type Rotation = Rotation3<f64>;
 
+// All randomness in the renderer comes from this generator, so a seed fully determines an image
+pub type RenderRng = Pcg64Mcg;
+
+// Every pixel sample gets its own generator, seeded from its position & index.
+// That keeps images identical regardless of which thread renders which sample, or in what order
+pub fn sample_rng(seed: u64, x: usize, y: usize, sample: u64) -> RenderRng {
+    let hash = mix(mix(mix(seed) ^ x as u64) ^ ((y as u64) << 32)) ^ sample;
+
+    RenderRng::seed_from_u64(mix(hash))
+}
+
+// The SplitMix64 finaliser, which spreads similar inputs over the whole output range
+#[inline]
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+    z ^ (z >> 31)
+}
+
 #[inline]
-pub fn random_range(min: f64, max: f64) -> f64 {
-    min + (max - min) * rand::random::<f64>()
+pub fn random_range<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> f64 {
+    min + (max - min) * rng.gen::<f64>()
 }
 
 #[inline]
-pub fn random_point(range: Range<f64>) -> Point {
+pub fn random_point<R: Rng + ?Sized>(rng: &mut R, range: Range<f64>) -> Point {
     Point::new(
-        thread_rng().gen_range(range.clone()),
-        thread_rng().gen_range(range.clone()),
-        thread_rng().gen_range(range),
+        rng.gen_range(range.clone()),
+        rng.gen_range(range.clone()),
+        rng.gen_range(range),
     )
 }
 
 #[inline]
-pub fn random_color(min: f64, max: f64) -> Color {
+pub fn random_color<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> Color {
     Color::new(
-        random_range(min, max),
-        random_range(min, max),
-        random_range(min, max),
+        random_range(rng, min, max),
+        random_range(rng, min, max),
+        random_range(rng, min, max),
     )
 }
 
 #[inline]
-pub fn random_vector_range(min: f64, max: f64) -> Vector {
+pub fn random_vector_range<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> Vector {
     Vector::new(
-        random_range(min, max),
-        random_range(min, max),
-        random_range(min, max),
+        random_range(rng, min, max),
+        random_range(rng, min, max),
+        random_range(rng, min, max),
     )
 }
 
-pub fn random_in_unit_disk() -> Vector {
+pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Vector {
     loop {
-        let p = Vector::new(random_range(-1.0, 1.0), random_range(-1.0, 1.0), 0.0);
+        let p = random_vector_range(rng, -1.0, 1.0);
         if p.magnitude_squared() < 1.0 {
             return p;
         }
     }
 }
 
-pub fn random_in_unit_sphere() -> Vector {
-    loop {
-        let p = random_vector_range(-1.0, 1.0);
-        if p.magnitude_squared() < 1.0 {
-            return p;
-        }
+#[inline]
+pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vector {
+    *Unit::new_normalize(random_in_unit_sphere(rng))
+}
+
+// Map a point in the unit square to the unit disk, keeping stratified points stratified
+// (Shirley & Chiu 1997, A Low Distortion Map Between Disk and Square)
+pub fn sample_unit_disk((u, v): (f64, f64)) -> Vector {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vector::new(0.0, 0.0, 0.0);
     }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, (PI / 4.0) * (b / a))
+    } else {
+        (b, (PI / 2.0) - (PI / 4.0) * (a / b))
+    };
+
+    Vector::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
-#[inline]
-pub fn random_unit_vector() -> Vector {
-    *Unit::new_normalize(random_in_unit_sphere())
+// Map a point in the unit square uniformly onto the surface of the unit sphere
+pub fn sample_unit_sphere((u, v): (f64, f64)) -> Vector {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-#[inline]
-pub fn random_in_hemisphere(normal: &Vector) -> Vector {
-    let in_unit_sphere = random_in_unit_sphere();
-    if in_unit_sphere.dot(normal) > 0.0 {
-        in_unit_sphere
-    } else {
-        -in_unit_sphere
-    }
+// Map a point in the unit cube uniformly into the unit ball
+pub fn sample_unit_ball(direction: (f64, f64), radius: f64) -> Vector {
+    radius.cbrt() * sample_unit_sphere(direction)
 }
 
-#[inline]
-pub fn near_zero(vector: &Vector) -> bool {
-    let s = 1e-8;
+// Map a point in the unit square onto the hemisphere around z, proportional to the cosine with z
+pub fn sample_cosine_hemisphere(sample: (f64, f64)) -> Vector {
+    let disk = sample_unit_disk(sample);
+    let z = (1.0 - disk.magnitude_squared()).max(0.0).sqrt();
+
+    Vector::new(disk.x, disk.y, z)
+}
+
+// Map a point in the unit square uniformly onto the cone of directions around z within the angle
+pub fn sample_cone((u, v): (f64, f64), cos_theta_max: f64) -> Vector {
+    let z = 1.0 + u * (cos_theta_max - 1.0);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Express a vector given relative to the z axis relative to the w axis instead
+pub fn to_basis(local: &Vector, w: &Vector) -> Vector {
+    let w = w.normalize();
+    let a = if w.x.abs() > 0.9 {
+        Vector::new(0.0, 1.0, 0.0)
+    } else {
+        Vector::new(1.0, 0.0, 0.0)
+    };
+    let v = w.cross(&a).normalize();
+    let u = w.cross(&v);
 
-    f64::abs(vector.x) < s && f64::abs(vector.y) < s && f64::abs(vector.z) < s
+    local.x * u + local.y * v + local.z * w
 }
@@ -0,0 +1,655 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::ImageError;
+
+use crate::{
+    hittable::HittableList,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    math::{Color, Point, Vector},
+    texture::ImageTexture,
+    triangle::{triangulate, MeshBuffers, MeshError, TriangleMesh},
+};
+
+// Used for faces before any usemtl, and for materials without a Kd
+const DEFAULT_DIFFUSE: f64 = 0.8;
+// Used for transparent materials without an Ni
+const DEFAULT_REFRACTION_INDEX: f64 = 1.5;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Syntax {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    UndefinedMaterial {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+    Image {
+        path: PathBuf,
+        line: usize,
+        image: PathBuf,
+        err: ImageError,
+    },
+    Mesh {
+        path: PathBuf,
+        err: MeshError,
+    },
+    NoFaces(PathBuf),
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Io { path, err } => write!(f, "couldn't read {}: {err}", path.display()),
+            ObjError::Syntax {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{line}: {message}", path.display()),
+            ObjError::UndefinedMaterial { path, line, name } => write!(
+                f,
+                "{}:{line}: there's no material named '{name}'",
+                path.display()
+            ),
+            ObjError::Image {
+                path,
+                line,
+                image,
+                err,
+            } => write!(
+                f,
+                "{}:{line}: couldn't load {}: {err}",
+                path.display(),
+                image.display()
+            ),
+            ObjError::Mesh { path, err } => write!(f, "{}: {err}", path.display()),
+            ObjError::NoFaces(path) => write!(f, "{} has no faces", path.display()),
+        }
+    }
+}
+
+impl Error for ObjError {}
+
+// Faces are split into a triangle mesh per object or group & material, ready to go under a BVH.
+// Material libraries & their textures are found relative to the file referring to them
+pub fn load(path: &Path) -> Result<HittableList, ObjError> {
+    parse(&read(path)?, path)
+}
+
+fn parse(source: &str, path: &Path) -> Result<HittableList, ObjError> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let mut positions: Vec<Point> = vec![];
+    let mut normals: Vec<Vector> = vec![];
+    let mut uvs: Vec<[f64; 2]> = vec![];
+    let mut library: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color::new(
+        DEFAULT_DIFFUSE,
+        DEFAULT_DIFFUSE,
+        DEFAULT_DIFFUSE,
+    )));
+    let mut material_name = String::new();
+    let mut object = String::new();
+    let mut group = String::new();
+    // Meshes are keyed by object, group & material, in the order they're first used
+    let mut meshes: Vec<MeshBuilder> = vec![];
+    let mut mesh_indices: HashMap<(String, String, String), usize> = HashMap::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let syntax = |message: String| ObjError::Syntax {
+            path: path.to_path_buf(),
+            line: line_number,
+            message,
+        };
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        match keyword {
+            // An optional w, or a vertex color after the position which isn't used
+            "v" => {
+                let vertex = numbers(&arguments, 3, 7).map_err(syntax)?;
+                if vertex.len() == 5 {
+                    return Err(syntax("expected 3, 4, 6 or 7 numbers, found 5".into()));
+                }
+                let [x, y, z] = vertex[..3] else {
+                    unreachable!()
+                };
+                positions.push(Point::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = numbers(&arguments, 3, 3).map_err(syntax)?[..] else {
+                    unreachable!()
+                };
+                // A zero normal is kept so the indices line up, shading falls back to the
+                // geometric normal where it's used
+                normals.push(
+                    Vector::new(x, y, z)
+                        .try_normalize(f64::EPSILON)
+                        .unwrap_or_else(Vector::zeros),
+                );
+            }
+            // The v coordinate is optional, & a third coordinate for 3D textures is ignored
+            "vt" => {
+                let uv = numbers(&arguments, 1, 3).map_err(syntax)?;
+                uvs.push([uv[0], uv.get(1).copied().unwrap_or(0.0)]);
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(syntax(format!(
+                        "a face needs at least 3 vertices, found {}",
+                        arguments.len()
+                    )));
+                }
+                let vertices = arguments
+                    .iter()
+                    .map(|vertex| {
+                        face_vertex(vertex, positions.len(), uvs.len(), normals.len())
+                            .map_err(syntax)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let key = (object.clone(), group.clone(), material_name.clone());
+                let index = *mesh_indices.entry(key).or_insert_with(|| {
+                    meshes.push(MeshBuilder::new(material.clone()));
+                    meshes.len() - 1
+                });
+                let corners: Vec<Point> = vertices.iter().map(|v| positions[v.0]).collect();
+                for triangle in triangulate(&corners) {
+                    meshes[index].add_triangle(
+                        triangle.map(|corner| vertices[corner]),
+                        &positions,
+                        &normals,
+                        &uvs,
+                    );
+                }
+            }
+            "o" => object = arguments.join(" "),
+            "g" => group = arguments.join(" "),
+            "mtllib" => {
+                // Names may contain spaces, in which case the whole rest of the line is one file
+                let names = match arguments.iter().all(|name| name.ends_with(".mtl")) {
+                    true => arguments.iter().map(|name| name.to_string()).collect(),
+                    false => vec![arguments.join(" ")],
+                };
+                for name in names {
+                    parse_mtl(&directory.join(name), &mut library)?;
+                }
+            }
+            "usemtl" => {
+                material_name = arguments.join(" ");
+                material = match materials.get(&material_name) {
+                    Some(material) => material.clone(),
+                    None => {
+                        let description = library.get(&material_name).ok_or_else(|| {
+                            ObjError::UndefinedMaterial {
+                                path: path.to_path_buf(),
+                                line: line_number,
+                                name: material_name.clone(),
+                            }
+                        })?;
+                        let built = description.build()?;
+                        materials.insert(material_name.clone(), built.clone());
+                        built
+                    }
+                };
+            }
+            // Smoothing groups, lines, points, curves & surfaces aren't supported
+            _ => {}
+        }
+    }
+
+    if meshes.is_empty() {
+        return Err(ObjError::NoFaces(path.to_path_buf()));
+    }
+
+    let mut list = HittableList::new();
+    for mesh in meshes {
+        let material = mesh.material.clone();
+        let buffers = mesh.finish().map_err(|err| ObjError::Mesh {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        list.add(Arc::new(TriangleMesh::new(Arc::new(buffers), material)));
+    }
+
+    Ok(list)
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|err| ObjError::Io {
+        path: path.to_path_buf(),
+        err,
+    })
+}
+
+fn numbers(arguments: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if arguments.len() < min || arguments.len() > max {
+        let expected = match min == max {
+            true => min.to_string(),
+            false => format!("{min} to {max}"),
+        };
+        return Err(format!(
+            "expected {expected} numbers, found {}",
+            arguments.len()
+        ));
+    }
+
+    arguments
+        .iter()
+        .map(|argument| {
+            argument
+                .parse()
+                .map_err(|_| format!("expected a number, found '{argument}'"))
+        })
+        .collect()
+}
+
+// Indices into the position, uv & normal lists, from v, v/vt, v//vn or v/vt/vn
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+fn face_vertex(
+    vertex: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Result<FaceVertex, String> {
+    let parts: Vec<&str> = vertex.split('/').collect();
+    if parts.len() > 3 {
+        return Err(format!(
+            "'{vertex}' isn't a face vertex like v, v/vt, v//vn or v/vt/vn"
+        ));
+    }
+    let optional = |part: Option<&&str>, count, kind| match part {
+        Some(part) if !part.is_empty() => resolve_index(part, count, kind).map(Some),
+        _ => Ok(None),
+    };
+
+    Ok((
+        resolve_index(parts[0], positions, "vertex")?,
+        optional(parts.get(1), uvs, "texture coordinate")?,
+        optional(parts.get(2), normals, "normal")?,
+    ))
+}
+
+// Indices start at 1, negative ones count back from the last defined so far
+fn resolve_index(index: &str, count: usize, kind: &str) -> Result<usize, String> {
+    let parsed: i64 = index
+        .parse()
+        .map_err(|_| format!("expected a {kind} index, found '{index}'"))?;
+    let resolved = match parsed {
+        0 => None,
+        1.. => Some(parsed - 1),
+        _ => Some(count as i64 + parsed),
+    };
+
+    match resolved {
+        Some(resolved) if (0..count as i64).contains(&resolved) => Ok(resolved as usize),
+        _ => Err(format!(
+            "{kind} index {parsed} is out of range, there are {count} so far"
+        )),
+    }
+}
+
+// OBJ vertices index positions, uvs & normals separately, so each distinct combination becomes
+// one vertex of the mesh
+struct MeshBuilder {
+    material: Arc<dyn Material>,
+    vertices: HashMap<FaceVertex, u32>,
+    positions: Vec<Point>,
+    normals: Vec<Vector>,
+    uvs: Vec<[f64; 2]>,
+    indices: Vec<[u32; 3]>,
+    // If any vertex lacks a normal or uv the mesh goes without them
+    missing_normals: bool,
+    missing_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn new(material: Arc<dyn Material>) -> Self {
+        Self {
+            material,
+            vertices: HashMap::new(),
+            positions: vec![],
+            normals: vec![],
+            uvs: vec![],
+            indices: vec![],
+            missing_normals: false,
+            missing_uvs: false,
+        }
+    }
+
+    fn add_triangle(
+        &mut self,
+        triangle: [FaceVertex; 3],
+        positions: &[Point],
+        normals: &[Vector],
+        uvs: &[[f64; 2]],
+    ) {
+        let indices = triangle.map(|vertex| {
+            *self.vertices.entry(vertex).or_insert_with(|| {
+                let (position, uv, normal) = vertex;
+                self.positions.push(positions[position]);
+                match normal {
+                    Some(normal) => self.normals.push(normals[normal]),
+                    None => self.missing_normals = true,
+                }
+                match uv {
+                    Some(uv) => self.uvs.push(uvs[uv]),
+                    None => self.missing_uvs = true,
+                }
+                self.positions.len() as u32 - 1
+            })
+        });
+        self.indices.push(indices);
+    }
+
+    fn finish(mut self) -> Result<MeshBuffers, MeshError> {
+        if self.missing_normals {
+            self.normals.clear();
+        }
+        if self.missing_uvs {
+            self.uvs.clear();
+        }
+
+        MeshBuffers::new(self.positions, self.normals, self.uvs, vec![], self.indices)
+    }
+}
+
+struct MtlMaterial {
+    path: PathBuf,
+    line: usize,
+    diffuse: Option<Color>,
+    specular: Color,
+    emission: Color,
+    specular_exponent: Option<f64>,
+    refraction_index: Option<f64>,
+    dissolve: f64,
+    illumination: Option<i64>,
+    diffuse_map: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    // The closest of the renderer's materials: emitters are lights, transparent or refracting
+    // illumination models are glass, reflective ones or ones more specular than diffuse are
+    // metal, & everything else is diffuse
+    fn build(&self) -> Result<Arc<dyn Material>, ObjError> {
+        let diffuse = self.diffuse.unwrap_or(Color::new(
+            DEFAULT_DIFFUSE,
+            DEFAULT_DIFFUSE,
+            DEFAULT_DIFFUSE,
+        ));
+        let illumination = self.illumination.unwrap_or(2);
+
+        // Only found by paths which hit them, they aren't sampled like the scene's lights
+        if self.emission.max() > 0.0 {
+            return Ok(Arc::new(DiffuseLight::new_from_color(self.emission)));
+        }
+        if self.dissolve < 1.0 || matches!(illumination, 4 | 6 | 7 | 9) {
+            return Ok(Arc::new(Dielectric::new(
+                self.refraction_index.unwrap_or(DEFAULT_REFRACTION_INDEX),
+            )));
+        }
+        if matches!(illumination, 3 | 5 | 8)
+            || self.diffuse_map.is_none() && self.specular.max() > diffuse.max()
+        {
+            // Blinn-Phong exponent to roughness
+            let fuzz = self
+                .specular_exponent
+                .map_or(0.0, |exponent| (2.0 / (exponent.max(0.0) + 2.0)).sqrt());
+            return Ok(Arc::new(Metal::new(self.specular, fuzz)));
+        }
+
+        Ok(match &self.diffuse_map {
+            Some(image) => {
+                let texture = ImageTexture::new(image).map_err(|err| ObjError::Image {
+                    path: self.path.clone(),
+                    line: self.line,
+                    image: image.clone(),
+                    err,
+                })?;
+                Arc::new(Lambertian::new(Arc::new(texture)))
+            }
+            None => Arc::new(Lambertian::new_from_color(diffuse)),
+        })
+    }
+}
+
+fn parse_mtl(path: &Path, library: &mut HashMap<String, MtlMaterial>) -> Result<(), ObjError> {
+    let source = read(path)?;
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let mut current: Option<String> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let syntax = |message: String| ObjError::Syntax {
+            path: path.to_path_buf(),
+            line: line_number,
+            message,
+        };
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = arguments.join(" ");
+            library.insert(
+                name.clone(),
+                MtlMaterial {
+                    path: path.to_path_buf(),
+                    line: line_number,
+                    diffuse: None,
+                    specular: Color::zeros(),
+                    emission: Color::zeros(),
+                    specular_exponent: None,
+                    refraction_index: None,
+                    dissolve: 1.0,
+                    illumination: None,
+                    diffuse_map: None,
+                },
+            );
+            current = Some(name);
+            continue;
+        }
+        if keyword.starts_with('#') {
+            continue;
+        }
+        let Some(material) = current.as_ref().and_then(|name| library.get_mut(name)) else {
+            return Err(syntax(format!("'{keyword}' before any newmtl")));
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = Some(color(&arguments).map_err(syntax)?),
+            "Ks" => material.specular = color(&arguments).map_err(syntax)?,
+            "Ke" => material.emission = color(&arguments).map_err(syntax)?,
+            "Ns" => {
+                material.specular_exponent = Some(numbers(&arguments, 1, 1).map_err(syntax)?[0])
+            }
+            "Ni" => material.refraction_index = Some(numbers(&arguments, 1, 1).map_err(syntax)?[0]),
+            "d" => material.dissolve = numbers(&arguments, 1, 1).map_err(syntax)?[0],
+            "Tr" => material.dissolve = 1.0 - numbers(&arguments, 1, 1).map_err(syntax)?[0],
+            "illum" => {
+                material.illumination = Some(
+                    arguments
+                        .first()
+                        .and_then(|model| model.parse().ok())
+                        .ok_or_else(|| syntax("expected an illumination model number".into()))?,
+                )
+            }
+            // Options like -s or -o come before the file name, which is taken to be the last
+            "map_Kd" => match arguments.last() {
+                Some(name) => material.diffuse_map = Some(directory.join(name)),
+                None => return Err(syntax("expected a texture file name".into())),
+            },
+            // Ambient color, other maps & the rest aren't supported
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// A grey level or r g b
+fn color(arguments: &[&str]) -> Result<Color, String> {
+    let color = numbers(arguments, 1, 3)?;
+    match color[..] {
+        [level] => Ok(Color::new(level, level, level)),
+        [r, g, b] => Ok(Color::new(r, g, b)),
+        _ => Err(format!("expected 1 or 3 numbers, found {}", color.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use rand::SeedableRng;
+
+    use crate::{
+        math::{Point, RenderRng, Vector},
+        ray::Ray,
+        scene_file::SceneExporter,
+    };
+
+    use super::{load, parse};
+
+    #[test]
+    pub fn groups_materials_and_attributes_are_loaded() {
+        // Unique to this run, so concurrent runs don't read each other's files
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let directory = std::env::temp_dir().join(format!(
+            "rust_tracer_obj_groups_materials_{}_{nanos}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        let earth = Path::new(env!("CARGO_MANIFEST_DIR")).join("textures/earthmap.jpg");
+        fs::write(
+            directory.join("materials.mtl"),
+            format!(
+                "newmtl red\nKd 0.8 0.1 0.1\n\n\
+                 newmtl glass\nKd 1 1 1\nNi 1.45\nd 0.1\n\n\
+                 newmtl mirror\nKs 0.9 0.9 0.9\nNs 1000\nillum 3\n\n\
+                 newmtl lamp\nKe 4 4 4\n\n\
+                 newmtl earth\nmap_Kd {}\n",
+                earth.display()
+            ),
+        )
+        .unwrap();
+        fs::write(
+            directory.join("model.obj"),
+            "mtllib materials.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             vn 0 0 1\n\
+             o floor\nusemtl red\nf 1/1/1 2/2/1 3/3/1 4/4/1\n\
+             usemtl glass\nf -4 -3 -2\n\
+             o lamp\nusemtl lamp\nf 1 2 3\n\
+             g mirror\nusemtl mirror\nf 1//1 2//1 3//1\n\
+             g globe\nusemtl earth\nf 1/1 2/2 3/3\n",
+        )
+        .unwrap();
+
+        let list = load(&directory.join("model.obj")).unwrap();
+        let mut exporter = SceneExporter::new(&directory);
+        let kinds: Vec<String> = list
+            .objects()
+            .iter()
+            .map(|object| {
+                let mut materials = vec![];
+                object.materials(&mut materials);
+                exporter.material(materials[0].as_ref())
+            })
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            vec![
+                "lambertian_1",
+                "dielectric_1",
+                "diffuse_light_1",
+                "metal_1",
+                "lambertian_2"
+            ],
+            kinds
+        );
+
+        // The quad is split into two triangles sharing its four vertices
+        let mut rng = RenderRng::seed_from_u64(0);
+        let ray = Ray::new(
+            Point::new(0.25, 0.75, 1.0),
+            Vector::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let hit = list.objects()[0]
+            .hit(&ray, 0.0, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.75).abs() < 1e-9);
+        assert!((hit.normal - Vector::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
+    }
+
+    #[test]
+    pub fn errors_give_the_line() {
+        let path = Path::new("model.obj");
+        let error = |source: &str| parse(source, path).err().unwrap().to_string();
+
+        assert_eq!(
+            "model.obj:4: vertex index 4 is out of range, there are 3 so far",
+            error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n")
+        );
+        assert_eq!(
+            "model.obj:1: expected a number, found 'x'",
+            error("v 0 x 0\n")
+        );
+        assert_eq!(
+            "model.obj:2: there's no material named 'gold'",
+            error("v 0 0 0\nusemtl gold\n")
+        );
+        assert_eq!("model.obj has no faces", error("v 0 0 0\n"));
+        assert_eq!(
+            "model.obj:1: expected 3, 4, 6 or 7 numbers, found 5",
+            error("v 0 0 0 1 1\n")
+        );
+    }
+
+    #[test]
+    pub fn vertex_colors_are_skipped() {
+        let source = "v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 1 0 0 1\nf 1 2 3\n";
+        let list = parse(source, Path::new("model.obj")).unwrap();
+
+        let mut rng = RenderRng::seed_from_u64(0);
+        let ray = Ray::new(
+            Point::new(0.25, 0.25, 1.0),
+            Vector::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let hit = list.objects()[0]
+            .hit(&ray, 0.0, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,587 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufWriter, Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use exr::prelude::{
+    f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds,
+    Layer, LayerAttributes, SmallVec, SpecificChannels, Vec2, WritableImage,
+};
+use image::{
+    codecs::{
+        hdr::HdrEncoder,
+        pnm::{PnmSubtype, SampleEncoding},
+    },
+    ImageError, ImageOutputFormat, Rgb, RgbImage,
+};
+
+use crate::{
+    film::Aov,
+    framebuffer::FrameBuffer,
+    math::Color,
+    tonemap::{heat_color, DisplayTransform, TransferFunction},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Tga,
+    Bmp,
+    /// Binary P6 PPM
+    Ppm,
+    /// ASCII P3 PPM
+    PpmAscii,
+    /// Linear OpenEXR
+    Exr,
+    /// Linear Radiance RGBE
+    Hdr,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> Result<Self, OutputError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        match extension.as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "jpg" | "jpeg" => Ok(OutputFormat::Jpeg),
+            "tga" => Ok(OutputFormat::Tga),
+            "bmp" => Ok(OutputFormat::Bmp),
+            "ppm" => Ok(OutputFormat::Ppm),
+            "exr" => Ok(OutputFormat::Exr),
+            "hdr" => Ok(OutputFormat::Hdr),
+            _ => Err(OutputError::UnknownExtension(path.display().to_string())),
+        }
+    }
+
+    fn image_format(self) -> Option<ImageOutputFormat> {
+        match self {
+            OutputFormat::Png => Some(ImageOutputFormat::Png),
+            OutputFormat::Jpeg => Some(ImageOutputFormat::Jpeg(95)),
+            OutputFormat::Tga => Some(ImageOutputFormat::Tga),
+            OutputFormat::Bmp => Some(ImageOutputFormat::Bmp),
+            OutputFormat::Ppm => Some(ImageOutputFormat::Pnm(PnmSubtype::Pixmap(
+                SampleEncoding::Binary,
+            ))),
+            OutputFormat::PpmAscii | OutputFormat::Exr | OutputFormat::Hdr => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+pub struct OutputSettings {
+    // Taken from the file extension if not set
+    pub format: Option<OutputFormat>,
+    pub exr_precision: ExrPrecision,
+    // Write an opaque alpha channel to EXR files
+    pub alpha: bool,
+    // Only applies to the 8 bit formats, HDR formats are always linear
+    pub display: DisplayTransform,
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    UnknownExtension(String),
+    Io(io::Error),
+    Image(ImageError),
+    Exr(exr::error::Error),
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputError::UnknownExtension(path) => {
+                write!(
+                    f,
+                    "can't pick an image format from the extension of '{path}'"
+                )
+            }
+            OutputError::Io(err) => write!(f, "{err}"),
+            OutputError::Image(err) => write!(f, "{err}"),
+            OutputError::Exr(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for OutputError {}
+
+impl From<io::Error> for OutputError {
+    fn from(err: io::Error) -> Self {
+        OutputError::Io(err)
+    }
+}
+
+impl From<ImageError> for OutputError {
+    fn from(err: ImageError) -> Self {
+        OutputError::Image(err)
+    }
+}
+
+impl From<exr::error::Error> for OutputError {
+    fn from(err: exr::error::Error) -> Self {
+        OutputError::Exr(err)
+    }
+}
+
+// Write the image to a file, or to stdout if no path is given.
+// The format is taken from the file extension unless one is passed explicitly
+pub fn write_image(
+    image: &FrameBuffer,
+    path: Option<&Path>,
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    let format = match (settings.format, path) {
+        (Some(format), _) => format,
+        (None, Some(path)) => OutputFormat::from_path(path)?,
+        (None, None) => OutputFormat::PpmAscii,
+    };
+
+    match path {
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(path)?);
+            encode(image, format, settings, &mut out)?;
+            out.flush()?;
+        }
+        None => {
+            // Most encoders need to seek, so build the whole file in memory first
+            let mut buffer = Cursor::new(vec![]);
+            encode(image, format, settings, &mut buffer)?;
+
+            let mut out = io::stdout().lock();
+            out.write_all(buffer.get_ref())?;
+            out.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+// Write the image next to the destination & move it into place,
+// so anything watching the file never sees a partly written image
+pub fn replace_image(
+    image: &FrameBuffer,
+    path: &Path,
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    let format = match settings.format {
+        Some(format) => format,
+        None => OutputFormat::from_path(path)?,
+    };
+
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    let tmp_path = path.with_file_name(file_name);
+
+    let mut out = BufWriter::new(File::create(&tmp_path)?);
+    encode(image, format, settings, &mut out)?;
+    out.flush()?;
+    drop(out);
+
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+// Write the image along with its AOVs. EXR output keeps them as layers of the one file
+// unless separate_files is set, otherwise each goes next to the image as <name>_<aov>.<ext>
+pub fn write_with_aovs(
+    image: &FrameBuffer,
+    aovs: &[(Aov, FrameBuffer)],
+    path: &Path,
+    separate_files: bool,
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    let format = match settings.format {
+        Some(format) => format,
+        None => OutputFormat::from_path(path)?,
+    };
+
+    if format == OutputFormat::Exr && !separate_files {
+        let mut out = BufWriter::new(File::create(path)?);
+        write_layered_exr(image, aovs, settings, &mut out)?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    write_image(image, Some(path), settings)?;
+    for (aov, aov_image) in aovs {
+        let aov_path = aov_path(path, *aov);
+        match format {
+            OutputFormat::Exr | OutputFormat::Hdr => {
+                write_image(aov_image, Some(&aov_path), settings)?
+            }
+            _ => match displayable_aov(*aov, aov_image) {
+                // Data passes are already in 0..1 & shouldn't be tone mapped
+                Some(preview) => {
+                    let preview_settings = OutputSettings {
+                        format: settings.format,
+                        exr_precision: settings.exr_precision,
+                        alpha: settings.alpha,
+                        display: DisplayTransform {
+                            transfer: TransferFunction::Linear,
+                            ..Default::default()
+                        },
+                    };
+                    write_image(&preview, Some(&aov_path), &preview_settings)?
+                }
+                None => write_image(aov_image, Some(&aov_path), settings)?,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+pub fn aov_name(aov: Aov) -> String {
+    aov.to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push("_");
+    file_name.push(aov_name(aov));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+
+    path.with_file_name(file_name)
+}
+
+// Map the passes that aren't colors into 0..1 for 8 bit formats.
+// Colors are left to the display transform
+fn displayable_aov(aov: Aov, image: &FrameBuffer) -> Option<FrameBuffer> {
+    let max = image
+        .pixels()
+        .iter()
+        .map(|pixel| pixel.x)
+        .fold(0.0, f64::max);
+    let normalize = |value: f64| if max > 0.0 { value / max } else { 0.0 };
+
+    let map: Box<dyn Fn(Color) -> Color> = match aov {
+        Aov::Normal => Box::new(|normal| (normal + Color::new(1.0, 1.0, 1.0)) / 2.0),
+        Aov::Depth => Box::new(|depth| depth.map(normalize)),
+        Aov::ObjectId | Aov::MaterialId => Box::new(|id| id_color(id.x as u32)),
+        Aov::SampleCount => Box::new(|samples| heat_color(normalize(samples.x))),
+        Aov::Albedo | Aov::Emission | Aov::Direct | Aov::Indirect => return None,
+    };
+    let pixels = image.pixels().iter().map(|&pixel| map(pixel)).collect();
+
+    Some(FrameBuffer::from_pixels(
+        image.width(),
+        image.height(),
+        pixels,
+    ))
+}
+
+// Give neighbouring IDs very different colors, with nothing (0) staying black
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let hash = id.wrapping_mul(0x9e37_79b9);
+    let channel = |shift: u32| 0.2 + 0.8 * ((hash >> shift) & 0xff) as f64 / 255.0;
+    Color::new(channel(24), channel(16), channel(8))
+}
+
+fn encode<W: Write + io::Seek>(
+    image: &FrameBuffer,
+    format: OutputFormat,
+    settings: &OutputSettings,
+    out: &mut W,
+) -> Result<(), OutputError> {
+    match format {
+        OutputFormat::PpmAscii => write_ppm_ascii(image, &settings.display, out)?,
+        OutputFormat::Exr => write_exr(image, settings, out)?,
+        OutputFormat::Hdr => write_hdr(image, out)?,
+        _ => {
+            if let Some(image_format) = format.image_format() {
+                to_rgb8(image, &settings.display).write_to(out, image_format)?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// HDR formats store the linear radiance as is, without clamping or gamma correction
+fn write_exr<W: Write + io::Seek>(
+    image: &FrameBuffer,
+    settings: &OutputSettings,
+    out: &mut W,
+) -> Result<(), exr::error::Error> {
+    let size = (image.width(), image.height());
+    let pixel = |Vec2(x, y): Vec2<usize>| image.get(x, y).cast::<f32>();
+
+    match (settings.exr_precision, settings.alpha) {
+        (ExrPrecision::Half, false) => {
+            let channels = SpecificChannels::rgb(|position| {
+                let color = pixel(position);
+                (
+                    f16::from_f32(color.x),
+                    f16::from_f32(color.y),
+                    f16::from_f32(color.z),
+                )
+            });
+            Image::from_channels(size, channels)
+                .write()
+                .to_buffered(out)
+        }
+        (ExrPrecision::Half, true) => {
+            let channels = SpecificChannels::rgba(|position| {
+                let color = pixel(position);
+                (
+                    f16::from_f32(color.x),
+                    f16::from_f32(color.y),
+                    f16::from_f32(color.z),
+                    f16::ONE,
+                )
+            });
+            Image::from_channels(size, channels)
+                .write()
+                .to_buffered(out)
+        }
+        (ExrPrecision::Float, false) => {
+            let channels = SpecificChannels::rgb(|position| {
+                let color = pixel(position);
+                (color.x, color.y, color.z)
+            });
+            Image::from_channels(size, channels)
+                .write()
+                .to_buffered(out)
+        }
+        (ExrPrecision::Float, true) => {
+            let channels = SpecificChannels::rgba(|position| {
+                let color = pixel(position);
+                (color.x, color.y, color.z, 1.0)
+            });
+            Image::from_channels(size, channels)
+                .write()
+                .to_buffered(out)
+        }
+    }
+}
+
+// The image is the first layer so readers which only look at one still find it.
+// IDs & sample counts are always full floats so they stay exact
+fn write_layered_exr<W: Write + io::Seek>(
+    image: &FrameBuffer,
+    aovs: &[(Aov, FrameBuffer)],
+    settings: &OutputSettings,
+    out: &mut W,
+) -> Result<(), exr::error::Error> {
+    let size = Vec2(image.width(), image.height());
+    let channel = |name: &str, values: Vec<f32>, exact: bool| {
+        let samples = match (settings.exr_precision, exact) {
+            (ExrPrecision::Half, false) => {
+                FlatSamples::F16(values.into_iter().map(f16::from_f32).collect())
+            }
+            _ => FlatSamples::F32(values),
+        };
+        AnyChannel::new(name, samples)
+    };
+    let rgb = |image: &FrameBuffer, alpha: bool| {
+        let mut channels = SmallVec::new();
+        for (i, name) in ["R", "G", "B"].into_iter().enumerate() {
+            let values = image.pixels().iter().map(|pixel| pixel[i] as f32).collect();
+            channels.push(channel(name, values, false));
+        }
+        if alpha {
+            channels.push(channel("A", vec![1.0; image.pixels().len()], false));
+        }
+        AnyChannels::sort(channels)
+    };
+    let single = |name: &str, image: &FrameBuffer, exact: bool| {
+        let values = image.pixels().iter().map(|pixel| pixel.x as f32).collect();
+        AnyChannels::sort(SmallVec::from_elem(channel(name, values, exact), 1))
+    };
+
+    let mut layers = vec![Layer::new(
+        size,
+        LayerAttributes::named("beauty"),
+        Encoding::FAST_LOSSLESS,
+        rgb(image, settings.alpha),
+    )];
+    for (aov, aov_image) in aovs {
+        let channels = match aov {
+            Aov::Depth => single("Z", aov_image, false),
+            Aov::ObjectId | Aov::MaterialId | Aov::SampleCount => single("Y", aov_image, true),
+            _ => rgb(aov_image, false),
+        };
+        layers.push(Layer::new(
+            size,
+            LayerAttributes::named(aov_name(*aov).as_str()),
+            Encoding::FAST_LOSSLESS,
+            channels,
+        ));
+    }
+
+    Image::from_layers(
+        ImageAttributes::new(IntegerBounds::from_dimensions(size)),
+        layers,
+    )
+    .write()
+    .to_buffered(out)
+}
+
+fn write_hdr(image: &FrameBuffer, out: &mut impl Write) -> Result<(), ImageError> {
+    let pixels: Vec<Rgb<f32>> = image
+        .pixels()
+        .iter()
+        .map(|pixel| Rgb([pixel.x as f32, pixel.y as f32, pixel.z as f32]))
+        .collect();
+
+    HdrEncoder::new(out).encode(&pixels, image.width(), image.height())
+}
+
+fn write_ppm_ascii(
+    image: &FrameBuffer,
+    display: &DisplayTransform,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(out, "P3")?;
+    writeln!(out, "{} {}", image.width(), image.height())?;
+    writeln!(out, "255")?;
+
+    for pixel in image.pixels() {
+        let [r, g, b] = display.to_rgb8(*pixel);
+        writeln!(out, "{r} {g} {b}")?;
+    }
+
+    Ok(())
+}
+
+fn to_rgb8(image: &FrameBuffer, display: &DisplayTransform) -> RgbImage {
+    let mut buffer = RgbImage::new(image.width() as u32, image.height() as u32);
+    for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+        pixel.0 = display.to_rgb8(image.get(x as usize, y as usize));
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{film::Aov, framebuffer::FrameBuffer, math::Color};
+
+    use super::{write_image, write_with_aovs, ExrPrecision, OutputFormat, OutputSettings};
+
+    #[test]
+    pub fn format_from_extension() {
+        assert_eq!(
+            OutputFormat::Png,
+            OutputFormat::from_path(Path::new("render.png")).unwrap()
+        );
+        assert_eq!(
+            OutputFormat::Jpeg,
+            OutputFormat::from_path(Path::new("render.JPG")).unwrap()
+        );
+        assert_eq!(
+            OutputFormat::Ppm,
+            OutputFormat::from_path(Path::new("out/render.ppm")).unwrap()
+        );
+        assert_eq!(
+            OutputFormat::Exr,
+            OutputFormat::from_path(Path::new("render.exr")).unwrap()
+        );
+        assert!(OutputFormat::from_path(Path::new("render")).is_err());
+        assert!(OutputFormat::from_path(Path::new("render.xyz")).is_err());
+    }
+
+    #[test]
+    pub fn exr_keeps_linear_values() {
+        let image = FrameBuffer::from_pixels(
+            2,
+            1,
+            vec![Color::new(0.25, 4.0, 100.0), Color::new(0.0, 0.5, 1.0)],
+        );
+        let path = std::env::temp_dir().join("rust_tracer_exr_keeps_linear_values.exr");
+        let settings = OutputSettings {
+            format: None,
+            exr_precision: ExrPrecision::Float,
+            alpha: false,
+            display: Default::default(),
+        };
+
+        write_image(&image, Some(&path), &settings).unwrap();
+        let read = exr::prelude::read_first_rgba_layer_from_file(
+            &path,
+            |resolution, _| vec![[0.0; 3]; resolution.width() * resolution.height()],
+            |pixels: &mut Vec<[f32; 3]>, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                pixels[position.y() * 2 + position.x()] = [r, g, b]
+            },
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            vec![[0.25, 4.0, 100.0], [0.0, 0.5, 1.0]],
+            read.layer_data.channel_data.pixels
+        );
+    }
+
+    #[test]
+    pub fn exr_aovs_are_layers() {
+        let image = FrameBuffer::from_pixels(2, 1, vec![Color::new(1.0, 2.0, 3.0); 2]);
+        let depth = FrameBuffer::from_pixels(
+            2,
+            1,
+            vec![Color::new(1.5, 1.5, 1.5), Color::new(8.0, 8.0, 8.0)],
+        );
+        let ids = FrameBuffer::from_pixels(2, 1, vec![Color::new(70001.0, 70001.0, 70001.0); 2]);
+        let path = std::env::temp_dir().join("rust_tracer_exr_aovs_are_layers.exr");
+        let settings = OutputSettings {
+            format: None,
+            exr_precision: ExrPrecision::Half,
+            alpha: false,
+            display: Default::default(),
+        };
+
+        write_with_aovs(
+            &image,
+            &[(Aov::Depth, depth), (Aov::ObjectId, ids)],
+            &path,
+            false,
+            &settings,
+        )
+        .unwrap();
+        let read = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let layer = |name: &str| {
+            read.layer_data
+                .iter()
+                .find(|layer| layer.attributes.layer_name == Some(name.into()))
+                .unwrap()
+        };
+        let values = |name: &str| -> Vec<f32> {
+            layer(name).channel_data.list[0]
+                .sample_data
+                .values_as_f32()
+                .collect()
+        };
+        assert_eq!(3, read.layer_data.len());
+        assert_eq!(3, layer("beauty").channel_data.list.len());
+        assert_eq!(vec![1.5, 8.0], values("depth"));
+        // Too big for a half float, so only exact as a full float
+        assert_eq!(vec![70001.0, 70001.0], values("object-id"));
+    }
+}
@@ -0,0 +1,155 @@
+use std::f64::consts::PI;
+
+use crate::{
+    hittable::Hittable,
+    math::{sample_cosine_hemisphere, sample_unit_sphere, to_basis, Point, RenderRng, Vector},
+    sampler::Sampler,
+    stats,
+};
+
+// A distribution of directions which can be both sampled & evaluated
+pub trait Pdf {
+    // The density, over solid angle, of generate picking direction
+    fn value(&self, direction: &Vector, rng: &mut RenderRng) -> f64;
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vector;
+}
+
+// Directions in the hemisphere around the normal, proportional to the cosine with it
+pub struct CosinePdf {
+    normal: Vector,
+}
+
+impl CosinePdf {
+    pub fn new(normal: Vector) -> Self {
+        Self { normal }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vector, _: &mut RenderRng) -> f64 {
+        let cosine = self.normal.normalize().dot(&direction.normalize());
+
+        cosine.max(0.0) / PI
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vector {
+        to_basis(&sample_cosine_hemisphere(sampler.get_2d()), &self.normal)
+    }
+}
+
+// Every direction equally likely
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _: &Vector, _: &mut RenderRng) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vector {
+        sample_unit_sphere(sampler.get_2d())
+    }
+}
+
+// Directions from origin towards a hittable, for sampling lights
+pub struct HittablePdf<'a> {
+    hittable: &'a dyn Hittable,
+    origin: Point,
+    time: f64,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(hittable: &'a dyn Hittable, origin: Point, time: f64) -> Self {
+        Self {
+            hittable,
+            origin,
+            time,
+        }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vector, rng: &mut RenderRng) -> f64 {
+        stats::uncounted(|| {
+            self.hittable
+                .pdf_value(self.origin, *direction, self.time, rng)
+        })
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vector {
+        self.hittable
+            .sample_direction(self.origin, self.time, sampler)
+    }
+}
+
+// Picks from first with probability weight, otherwise from second
+pub struct MixturePdf<'a> {
+    first: &'a dyn Pdf,
+    second: &'a dyn Pdf,
+    weight: f64,
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(first: &'a dyn Pdf, second: &'a dyn Pdf, weight: f64) -> Self {
+        Self {
+            first,
+            second,
+            weight,
+        }
+    }
+}
+
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: &Vector, rng: &mut RenderRng) -> f64 {
+        self.weight * self.first.value(direction, rng)
+            + (1.0 - self.weight) * self.second.value(direction, rng)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vector {
+        if sampler.get_1d() < self.weight {
+            self.first.generate(sampler)
+        } else {
+            self.second.generate(sampler)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use crate::{
+        math::{RenderRng, Vector},
+        sampler::SamplerKind,
+    };
+
+    use super::{CosinePdf, MixturePdf, Pdf, SpherePdf};
+
+    // Estimate the integral of the pdf over the sphere, & check its samples have density
+    fn check_normalised(pdf: &dyn Pdf) {
+        let mut rng = RenderRng::seed_from_u64(0);
+        let mut sampler = SamplerKind::Independent.build(0, 1);
+        let uniform = SpherePdf;
+        let count = 100_000;
+
+        let mut integral = 0.0;
+        for i in 0..count {
+            sampler.start_sample(0, 0, i);
+            let direction = uniform.generate(sampler.as_mut());
+            integral += pdf.value(&direction, &mut rng) / uniform.value(&direction, &mut rng);
+
+            let sampled = pdf.generate(sampler.as_mut());
+            assert!(pdf.value(&sampled, &mut rng) > 0.0);
+        }
+
+        assert!((integral / count as f64 - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    pub fn pdfs_integrate_to_one() {
+        let cosine = CosinePdf::new(Vector::new(0.0, 2.0, 1.0));
+
+        check_normalised(&cosine);
+        check_normalised(&SpherePdf);
+        check_normalised(&MixturePdf::new(&cosine, &SpherePdf, 0.3));
+    }
+}
@@ -13,14 +13,14 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    pub fn new() -> Self {
-        let ran = (0..POINT_COUNT).map(|_| random_unit_vector()).collect();
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let ran = (0..POINT_COUNT).map(|_| random_unit_vector(rng)).collect();
 
         Self {
             ran,
-            perm_x: Self::generate_perm(),
-            perm_y: Self::generate_perm(),
-            perm_z: Self::generate_perm(),
+            perm_x: Self::generate_perm(rng),
+            perm_y: Self::generate_perm(rng),
+            perm_z: Self::generate_perm(rng),
         }
     }
 
@@ -64,20 +64,18 @@ impl Perlin {
         Self::perlin_interpolate(&c, u, v, w)
     }
 
-    fn generate_perm() -> Vec<usize> {
+    fn generate_perm<R: Rng + ?Sized>(rng: &mut R) -> Vec<usize> {
         let mut p: Vec<usize> = (0..POINT_COUNT).collect();
 
-        Self::permute(&mut p);
+        Self::permute(&mut p, rng);
 
         p
     }
 
-    fn permute(p: &mut [usize]) {
+    fn permute<R: Rng + ?Sized>(p: &mut [usize], rng: &mut R) {
         for i in (0..p.len() - 1).rev() {
-            let target = rand::thread_rng().gen_range(0..i + 1);
-            let tmp = p[i];
-            p[i] = p[target];
-            p[target] = tmp;
+            let target = rng.gen_range(0..i + 1);
+            p.swap(i, target);
         }
     }
 
@@ -0,0 +1,602 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    material::Material,
+    math::{Color, Point, Vector},
+    triangle::{triangulate, MeshBuffers, MeshError, TriangleMesh},
+};
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Header {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    // Which element the data was being read for, counting from 0
+    Data {
+        path: PathBuf,
+        element: String,
+        index: usize,
+        message: String,
+    },
+    Mesh {
+        path: PathBuf,
+        err: MeshError,
+    },
+    NoFaces(PathBuf),
+}
+
+impl Display for PlyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlyError::Io { path, err } => write!(f, "couldn't read {}: {err}", path.display()),
+            PlyError::Header {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{line}: {message}", path.display()),
+            PlyError::Data {
+                path,
+                element,
+                index,
+                message,
+            } => write!(f, "{}: {element} {index}: {message}", path.display()),
+            PlyError::Mesh { path, err } => write!(f, "{}: {err}", path.display()),
+            PlyError::NoFaces(path) => write!(f, "{} has no faces", path.display()),
+        }
+    }
+}
+
+impl Error for PlyError {}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    Int8,
+    Uint8,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+    Float64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::Int8,
+            "uchar" | "uint8" => Scalar::Uint8,
+            "short" | "int16" => Scalar::Int16,
+            "ushort" | "uint16" => Scalar::Uint16,
+            "int" | "int32" => Scalar::Int32,
+            "uint" | "uint32" => Scalar::Uint32,
+            "float" | "float32" => Scalar::Float32,
+            "double" | "float64" => Scalar::Float64,
+            _ => return None,
+        })
+    }
+
+    // Integer colors use their whole range, so this maps them onto [0, 1]
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::Uint8 => 1.0 / u8::MAX as f64,
+            Scalar::Uint16 => 1.0 / u16::MAX as f64,
+            _ => 1.0,
+        }
+    }
+}
+
+enum PropertyKind {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Vertices need positions, the rest are used if they're there. Faces are lists of vertex indices
+pub fn load(path: &Path, material: Arc<dyn Material>) -> Result<TriangleMesh, PlyError> {
+    let data = fs::read(path).map_err(|err| PlyError::Io {
+        path: path.to_path_buf(),
+        err,
+    })?;
+
+    parse(&data, path, material)
+}
+
+fn parse(data: &[u8], path: &Path, material: Arc<dyn Material>) -> Result<TriangleMesh, PlyError> {
+    let (format, elements, body) = parse_header(data, path)?;
+    let mut body = match format {
+        Format::Ascii => match std::str::from_utf8(body) {
+            Ok(text) => Body::Ascii(text.split_ascii_whitespace()),
+            Err(_) => {
+                return Err(PlyError::Header {
+                    path: path.to_path_buf(),
+                    line: 2,
+                    message: "the data of an ascii file isn't text".into(),
+                })
+            }
+        },
+        _ => Body::Binary {
+            data: body,
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut colors = vec![];
+    let mut indices = vec![];
+    let mut values = vec![];
+    for element in &elements {
+        let data_error = |index: usize, message: String| PlyError::Data {
+            path: path.to_path_buf(),
+            element: element.name.clone(),
+            index,
+            message,
+        };
+
+        match element.name.as_str() {
+            "vertex" => {
+                let layout = VertexLayout::new(element)
+                    .ok_or_else(|| data_error(0, "vertices need x, y & z properties".into()))?;
+                for index in 0..element.count {
+                    body.read_element(element, &mut values)
+                        .map_err(|message| data_error(index, message))?;
+                    let get = |properties: [usize; 3]| properties.map(|property| values[property]);
+
+                    let [x, y, z] = get(layout.position);
+                    positions.push(Point::new(x, y, z));
+                    if let Some(normal) = layout.normal {
+                        let [x, y, z] = get(normal);
+                        // A zero normal is kept so the rest line up, shading falls back to
+                        // the geometric normal where it's used
+                        normals.push(
+                            Vector::new(x, y, z)
+                                .try_normalize(f64::EPSILON)
+                                .unwrap_or_else(Vector::zeros),
+                        );
+                    }
+                    if let Some((color, scale)) = layout.color {
+                        let [r, g, b] = get(color);
+                        colors.push(Color::new(r, g, b) * scale);
+                    }
+                    if let Some([u, v]) = layout.uv {
+                        uvs.push([values[u], values[v]]);
+                    }
+                }
+            }
+            "face" => {
+                let list = element
+                    .properties
+                    .iter()
+                    .position(|property| {
+                        matches!(property.name.as_str(), "vertex_indices" | "vertex_index")
+                            && matches!(property.kind, PropertyKind::List { .. })
+                    })
+                    .ok_or_else(|| data_error(0, "faces need a vertex_indices list".into()))?;
+                for index in 0..element.count {
+                    let face = body
+                        .read_face(element, list, positions.len())
+                        .map_err(|message| data_error(index, message))?;
+                    let corners: Vec<Point> = face
+                        .iter()
+                        .map(|&vertex| positions[vertex as usize])
+                        .collect();
+                    for triangle in triangulate(&corners) {
+                        indices.push(triangle.map(|corner| face[corner]));
+                    }
+                }
+            }
+            // Other elements like edges & materials are read past
+            _ => {
+                for index in 0..element.count {
+                    body.read_element(element, &mut values)
+                        .map_err(|message| data_error(index, message))?;
+                }
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(PlyError::NoFaces(path.to_path_buf()));
+    }
+    let buffers = MeshBuffers::new(positions, normals, uvs, colors, indices).map_err(|err| {
+        PlyError::Mesh {
+            path: path.to_path_buf(),
+            err,
+        }
+    })?;
+
+    Ok(TriangleMesh::new(Arc::new(buffers), material))
+}
+
+// Gives the format, the elements in the order their data comes in & the data after the header
+fn parse_header<'a>(
+    data: &'a [u8],
+    path: &Path,
+) -> Result<(Format, Vec<Element>, &'a [u8]), PlyError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut start = 0;
+    let mut line_number = 0;
+
+    loop {
+        line_number += 1;
+        let error = |message: &str| PlyError::Header {
+            path: path.to_path_buf(),
+            line: line_number,
+            message: message.to_string(),
+        };
+        let end = data[start..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map(|end| start + end)
+            .ok_or_else(|| error("the header has no end_header"))?;
+        let line = String::from_utf8_lossy(&data[start..end]);
+        start = end + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err(error("this isn't a PLY file, it doesn't start with 'ply'"));
+            }
+            continue;
+        }
+
+        match tokens[..] {
+            ["format", name, _] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error(&format!("unknown format '{name}'"))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(&format!("expected an element count, found '{count}'")))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => {
+                let kind = PropertyKind::List {
+                    count: scalar(count).map_err(|message| error(&message))?,
+                    item: scalar(item).map_err(|message| error(&message))?,
+                };
+                add_property(&mut elements, name, kind).map_err(|message| error(&message))?;
+            }
+            ["property", kind, name] => {
+                let kind = PropertyKind::Scalar(scalar(kind).map_err(|message| error(&message))?);
+                add_property(&mut elements, name, kind).map_err(|message| error(&message))?;
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(error(&format!("unexpected '{}'", line.trim()))),
+        }
+    }
+
+    let format = format.ok_or_else(|| PlyError::Header {
+        path: path.to_path_buf(),
+        line: 2,
+        message: "the header has no format".into(),
+    })?;
+
+    Ok((format, elements, &data[start..]))
+}
+
+fn scalar(name: &str) -> Result<Scalar, String> {
+    Scalar::parse(name).ok_or_else(|| format!("unknown property type '{name}'"))
+}
+
+fn add_property(elements: &mut [Element], name: &str, kind: PropertyKind) -> Result<(), String> {
+    let element = elements
+        .last_mut()
+        .ok_or_else(|| format!("property '{name}' comes before any element"))?;
+    element.properties.push(Property {
+        name: name.to_string(),
+        kind,
+    });
+
+    Ok(())
+}
+
+// Which of a vertex's properties hold each attribute
+struct VertexLayout {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    color: Option<([usize; 3], f64)>,
+    uv: Option<[usize; 2]>,
+}
+
+impl VertexLayout {
+    fn new(element: &Element) -> Option<Self> {
+        let find = |names: &[&str]| {
+            element.properties.iter().position(|property| {
+                names.contains(&property.name.as_str())
+                    && matches!(property.kind, PropertyKind::Scalar(_))
+            })
+        };
+        let find_all = |names: [&[&str]; 3]| -> Option<[usize; 3]> {
+            Some([find(names[0])?, find(names[1])?, find(names[2])?])
+        };
+
+        let color = find_all([&["red", "r"], &["green", "g"], &["blue", "b"]]).map(|color| {
+            let scale = match element.properties[color[0]].kind {
+                PropertyKind::Scalar(scalar) => scalar.color_scale(),
+                PropertyKind::List { .. } => 1.0,
+            };
+            (color, scale)
+        });
+        let uv = find(&["u", "s", "texture_u", "texture_s"])
+            .zip(find(&["v", "t", "texture_v", "texture_t"]))
+            .map(|(u, v)| [u, v]);
+
+        Some(Self {
+            position: find_all([&["x"], &["y"], &["z"]])?,
+            normal: find_all([&["nx"], &["ny"], &["nz"]]),
+            color,
+            uv,
+        })
+    }
+}
+
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+// Reads a binary number of the type in the endianness of the file
+macro_rules! read_binary {
+    ($data:expr, $big_endian:expr, $type:ty) => {{
+        const SIZE: usize = std::mem::size_of::<$type>();
+        if $data.len() < SIZE {
+            return Err("the file ends early".into());
+        }
+        let (bytes, rest) = $data.split_at(SIZE);
+        *$data = rest;
+        let bytes: [u8; SIZE] = bytes.try_into().unwrap();
+        match $big_endian {
+            true => <$type>::from_be_bytes(bytes) as f64,
+            false => <$type>::from_le_bytes(bytes) as f64,
+        }
+    }};
+}
+
+impl<'a> Body<'a> {
+    fn scalar(&mut self, scalar: Scalar) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or("the file ends early")?;
+                token
+                    .parse()
+                    .map_err(|_| format!("expected a number, found '{token}'"))
+            }
+            Body::Binary { data, big_endian } => Ok(match scalar {
+                Scalar::Int8 => read_binary!(data, *big_endian, i8),
+                Scalar::Uint8 => read_binary!(data, *big_endian, u8),
+                Scalar::Int16 => read_binary!(data, *big_endian, i16),
+                Scalar::Uint16 => read_binary!(data, *big_endian, u16),
+                Scalar::Int32 => read_binary!(data, *big_endian, i32),
+                Scalar::Uint32 => read_binary!(data, *big_endian, u32),
+                Scalar::Float32 => read_binary!(data, *big_endian, f32),
+                Scalar::Float64 => read_binary!(data, *big_endian, f64),
+            }),
+        }
+    }
+
+    fn count(&mut self, scalar: Scalar) -> Result<usize, String> {
+        let count = self.scalar(scalar)?;
+        match count >= 0.0 && count.fract() == 0.0 {
+            true => Ok(count as usize),
+            false => Err(format!("{count} isn't a list length")),
+        }
+    }
+
+    // Scalar properties are put in values, lists are read past
+    fn read_element(&mut self, element: &Element, values: &mut Vec<f64>) -> Result<(), String> {
+        values.clear();
+        for property in &element.properties {
+            match property.kind {
+                PropertyKind::Scalar(scalar) => values.push(self.scalar(scalar)?),
+                PropertyKind::List { count, item } => {
+                    for _ in 0..self.count(count)? {
+                        self.scalar(item)?;
+                    }
+                    values.push(0.0);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The vertex indices of the list property, checked against the vertices read so far
+    fn read_face(
+        &mut self,
+        element: &Element,
+        list: usize,
+        vertices: usize,
+    ) -> Result<Vec<u32>, String> {
+        let mut face = vec![];
+        for (i, property) in element.properties.iter().enumerate() {
+            match property.kind {
+                PropertyKind::Scalar(scalar) => {
+                    self.scalar(scalar)?;
+                }
+                PropertyKind::List { count, item } => {
+                    for _ in 0..self.count(count)? {
+                        let index = self.scalar(item)?;
+                        if i == list {
+                            if index < 0.0 || index.fract() != 0.0 || index >= vertices as f64 {
+                                return Err(format!(
+                                    "vertex {index} doesn't exist, there are {vertices}"
+                                ));
+                            }
+                            face.push(index as u32);
+                        }
+                    }
+                }
+            }
+        }
+
+        match face.len() {
+            0..=2 => Err(format!(
+                "a face needs at least 3 vertices, found {}",
+                face.len()
+            )),
+            _ => Ok(face),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use rand::SeedableRng;
+
+    use crate::{
+        hittable::Hittable,
+        material::Lambertian,
+        math::{Color, Point, RenderRng, Vector},
+        ray::Ray,
+    };
+
+    use super::parse;
+
+    // A unit square in z = 0 with a colored corner each, followed by an edge to be skipped
+    fn square(format: &str) -> Vec<u8> {
+        let mut data = format!(
+            "ply\nformat {format} 1.0\ncomment a colored square\n\
+             element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face 1\nproperty list uchar int vertex_indices\nproperty uchar flags\n\
+             element edge 1\nproperty int vertex1\nproperty int vertex2\nend_header\n"
+        )
+        .into_bytes();
+        let vertices = [
+            ([0.0f32, 0.0], [255u8, 0, 0]),
+            ([1.0, 0.0], [0, 255, 0]),
+            ([1.0, 1.0], [0, 0, 255]),
+            ([0.0, 1.0], [255, 255, 255]),
+        ];
+
+        if format == "ascii" {
+            for ([x, y], [r, g, b]) in vertices {
+                data.extend(format!("{x} {y} 0 0 0 1 {r} {g} {b}\n").bytes());
+            }
+            data.extend(b"4 0 1 2 3 7\n0 2\n");
+            return data;
+        }
+
+        let big_endian = format == "binary_big_endian";
+        let float = |value: f32| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let int = |value: i32| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        for ([x, y], color) in vertices {
+            for value in [x, y, 0.0, 0.0, 0.0, 1.0] {
+                data.extend(float(value));
+            }
+            data.extend(color);
+        }
+        data.push(4);
+        for index in 0..4 {
+            data.extend(int(index));
+        }
+        data.push(7);
+        data.extend(int(0));
+        data.extend(int(2));
+
+        data
+    }
+
+    #[test]
+    pub fn ascii_and_binary_files_load_the_same() {
+        let material = Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5)));
+        let mut rng = RenderRng::seed_from_u64(0);
+
+        for format in ["ascii", "binary_little_endian", "binary_big_endian"] {
+            let mesh = parse(&square(format), Path::new("square.ply"), material.clone()).unwrap();
+            let ray = Ray::new(
+                Point::new(0.25, 0.25, 1.0),
+                Vector::new(0.0, 0.0, -1.0),
+                0.0,
+            );
+            let hit = mesh.hit(&ray, 0.0, f64::INFINITY, &mut rng).unwrap();
+
+            // Between the corners nearest, weighted by how near they are
+            let color = hit.vertex_color.unwrap();
+            assert!(
+                (color - Color::new(0.75, 0.5, 0.25)).magnitude() < 1e-6,
+                "{format}: {color}"
+            );
+            assert!((hit.normal - Vector::new(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+            assert!((hit.t - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    pub fn errors_say_where() {
+        let material = Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5)));
+        let error = |data: &[u8]| {
+            parse(data, Path::new("square.ply"), material.clone())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            "square.ply:1: this isn't a PLY file, it doesn't start with 'ply'",
+            error(b"OFF\n")
+        );
+        assert_eq!(
+            "square.ply:4: unknown property type 'quad'",
+            error(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty quad x\nend_header\n")
+        );
+
+        let mut truncated = square("binary_little_endian");
+        truncated.truncate(truncated.len() - 20);
+        assert_eq!("square.ply: face 0: the file ends early", error(&truncated));
+
+        let ascii = String::from_utf8(square("ascii")).unwrap();
+        assert_eq!(
+            "square.ply: face 0: vertex 9 doesn't exist, there are 4",
+            error(ascii.replace("4 0 1 2 3 7", "4 0 1 2 9 7").as_bytes())
+        );
+    }
+}
@@ -4,14 +4,18 @@ use crate::{
     aabb::AABB,
     hittable::{HitRecord, Hittable, HittableList},
     material::Material,
-    math::{Point, Vector},
+    math::{Point, RenderRng, Vector},
     ray::Ray,
+    sampler::Sampler,
+    scene_file::{ObjectDescription, SceneExporter},
+    stats, MIN_INTERSECTION_DISTANCE,
 };
 
 pub struct Cuboid {
     min: Point,
     max: Point,
     sides: HittableList,
+    material: Arc<dyn Material>,
 }
 
 impl Cuboid {
@@ -73,18 +77,39 @@ impl Cuboid {
             min: p0,
             max: p1,
             sides,
+            material,
         }
     }
 }
 
 impl Hittable for Cuboid {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.sides.hit(ray, t_min, t_max)
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        self.sides.hit(ray, t_min, t_max, rng)
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
         Some(AABB::new(self.min, self.max))
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        self.sides.materials(materials);
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        self.sides.pdf_value(origin, direction, time, rng)
+    }
+
+    fn sample_direction(&self, origin: Point, time: f64, sampler: &mut dyn Sampler) -> Vector {
+        self.sides.sample_direction(origin, time, sampler)
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::Cuboid {
+            min: self.min,
+            max: self.max,
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
 }
 
 pub struct XyRectangle {
@@ -110,7 +135,8 @@ impl XyRectangle {
 }
 
 impl Hittable for XyRectangle {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _: &mut RenderRng) -> Option<HitRecord> {
+        stats::count_primitive_test();
         // Calculate hit position of the xy-plane along the ray
         let t = (self.k - ray.origin().z) / ray.direction().z;
         if t < t_min || t > t_max {
@@ -140,6 +166,43 @@ impl Hittable for XyRectangle {
             Point::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.material.clone());
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        let hit = self.hit(
+            &Ray::new(origin, direction, time),
+            MIN_INTERSECTION_DISTANCE,
+            f64::INFINITY,
+            rng,
+        );
+
+        rectangle_pdf(hit, direction, area)
+    }
+
+    fn sample_direction(&self, origin: Point, _: f64, sampler: &mut dyn Sampler) -> Vector {
+        let (u, v) = sampler.get_2d();
+
+        Point::new(
+            self.x0 + u * (self.x1 - self.x0),
+            self.y0 + v * (self.y1 - self.y0),
+            self.k,
+        ) - origin
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::XyRectangle {
+            x0: self.x0,
+            x1: self.x1,
+            y0: self.y0,
+            y1: self.y1,
+            k: self.k,
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
 }
 
 pub struct XzRectangle {
@@ -165,7 +228,8 @@ impl XzRectangle {
 }
 
 impl Hittable for XzRectangle {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _: &mut RenderRng) -> Option<HitRecord> {
+        stats::count_primitive_test();
         // Calculate hit position of the xy-plane along the ray
         let t = (self.k - ray.origin().y) / ray.direction().y;
         if t < t_min || t > t_max {
@@ -195,6 +259,43 @@ impl Hittable for XzRectangle {
             Point::new(self.x1, self.k + 0.0001, self.z1),
         ))
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.material.clone());
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        let hit = self.hit(
+            &Ray::new(origin, direction, time),
+            MIN_INTERSECTION_DISTANCE,
+            f64::INFINITY,
+            rng,
+        );
+
+        rectangle_pdf(hit, direction, area)
+    }
+
+    fn sample_direction(&self, origin: Point, _: f64, sampler: &mut dyn Sampler) -> Vector {
+        let (u, v) = sampler.get_2d();
+
+        Point::new(
+            self.x0 + u * (self.x1 - self.x0),
+            self.k,
+            self.z0 + v * (self.z1 - self.z0),
+        ) - origin
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::XzRectangle {
+            x0: self.x0,
+            x1: self.x1,
+            z0: self.z0,
+            z1: self.z1,
+            k: self.k,
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
 }
 
 pub struct YzRectangle {
@@ -220,7 +321,8 @@ impl YzRectangle {
 }
 
 impl Hittable for YzRectangle {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _: &mut RenderRng) -> Option<HitRecord> {
+        stats::count_primitive_test();
         // Calculate hit position of the xy-plane along the ray
         let t = (self.k - ray.origin().x) / ray.direction().x;
         if t < t_min || t > t_max {
@@ -250,4 +352,54 @@ impl Hittable for YzRectangle {
             Point::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.material.clone());
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        let hit = self.hit(
+            &Ray::new(origin, direction, time),
+            MIN_INTERSECTION_DISTANCE,
+            f64::INFINITY,
+            rng,
+        );
+
+        rectangle_pdf(hit, direction, area)
+    }
+
+    fn sample_direction(&self, origin: Point, _: f64, sampler: &mut dyn Sampler) -> Vector {
+        let (u, v) = sampler.get_2d();
+
+        Point::new(
+            self.k,
+            self.y0 + u * (self.y1 - self.y0),
+            self.z0 + v * (self.z1 - self.z0),
+        ) - origin
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::YzRectangle {
+            y0: self.y0,
+            y1: self.y1,
+            z0: self.z0,
+            z1: self.z1,
+            k: self.k,
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
+}
+
+// Convert the density of a uniformly sampled point on a rectangle from area to solid angle
+fn rectangle_pdf(hit: Option<HitRecord>, direction: Vector, area: f64) -> f64 {
+    match hit {
+        Some(hit) => {
+            let distance_squared = hit.t.powi(2) * direction.magnitude_squared();
+            let cosine = direction.dot(&hit.normal).abs() / direction.magnitude();
+
+            distance_squared / (cosine * area)
+        }
+        None => 0.0,
+    }
 }
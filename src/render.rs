@@ -0,0 +1,677 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+use crate::{
+    bvh::BVHNode,
+    camera::Camera,
+    film::{AovSample, Film, Tile},
+    filter::Filter,
+    hittable::HittableList,
+    instance::Identified,
+    integrator::{Environment, Integrator, IntegratorKind, LightSampling, LuminanceClamp},
+    material::material_key,
+    math::{Color, RenderRng},
+    sampler::SamplerKind,
+    scene::Scene,
+    stats::{self, RayCounts, RenderStats},
+};
+
+pub struct RenderSettings {
+    pub integrator: IntegratorKind,
+    pub min_depth: i64,
+    pub max_depth: i64,
+    pub tile_size: usize,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub light_sampling: LightSampling,
+    pub clamp: LuminanceClamp,
+    pub filter: Filter,
+    // The samples per pixel the render is aiming for, which sizes the sampler's strata
+    pub samples_per_pixel: u64,
+    // Keep adding passes until this is up instead of stopping at samples_per_pixel,
+    // never starting one which looks like it would run past it
+    pub time_limit: Option<Duration>,
+}
+
+pub struct AdaptiveSettings {
+    pub min_samples: u64,
+    pub max_samples: u64,
+    // Pixels stop being sampled once their relative error is below this
+    pub threshold: f64,
+}
+
+pub struct Renderer {
+    world: HittableList,
+    lights: HittableList,
+    material_ids: HashMap<usize, u32>,
+    integrator: Box<dyn Integrator>,
+    camera: Camera,
+    background: Color,
+    width: usize,
+    height: usize,
+    settings: RenderSettings,
+    bvh_build_time: Duration,
+    // Work done by every tile rendered so far
+    counts: Mutex<RayCounts>,
+}
+
+impl Renderer {
+    pub fn new(scene: Scene, settings: RenderSettings) -> Self {
+        // Number the objects & materials for the AOVs, in the order they appear in the scene
+        let mut objects = HittableList::new();
+        let mut materials = vec![];
+        for (index, object) in scene.objects.into_raw().into_iter().enumerate() {
+            object.materials(&mut materials);
+            objects.add(Arc::new(Identified::new(object, index as u32 + 1)));
+        }
+        let mut material_ids = HashMap::new();
+        for material in materials {
+            let next_id = material_ids.len() as u32 + 1;
+            material_ids
+                .entry(material_key(&material))
+                .or_insert(next_id);
+        }
+
+        let bvh_start = Instant::now();
+        let bvh = BVHNode::new(
+            objects,
+            0.0,
+            1.0,
+            &mut RenderRng::seed_from_u64(settings.seed),
+        );
+        let bvh_build_time = bvh_start.elapsed();
+        // An empty scene is just the background
+        let mut world = HittableList::new();
+        if let Some(bvh) = bvh {
+            world.add(Arc::new(bvh));
+        }
+
+        Self {
+            world,
+            lights: scene.lights,
+            material_ids,
+            integrator: settings.integrator.build(
+                settings.min_depth,
+                settings.max_depth,
+                settings.light_sampling,
+                settings.clamp,
+            ),
+            camera: scene.camera,
+            background: scene.background,
+            width: scene.image.width as usize,
+            height: scene.image.height as usize,
+            settings,
+            bvh_build_time,
+            counts: Mutex::new(RayCounts::default()),
+        }
+    }
+
+    // Stats for everything rendered so far, taking render_time to have done it in
+    pub fn stats(&self, render_time: Duration) -> RenderStats {
+        RenderStats::new(
+            *self.counts.lock().unwrap(),
+            self.bvh_build_time,
+            render_time,
+        )
+    }
+
+    pub fn film(&self, aovs: bool) -> Film {
+        match aovs {
+            true => Film::with_aovs(self.width, self.height, self.settings.filter),
+            false => Film::new(self.width, self.height, self.settings.filter),
+        }
+    }
+
+    // Add plan[i] samples to pixel i of the film, carrying on from the samples already in it.
+    // Tiles are handed out to the rayon pool, then merged into the film in order so
+    // splats overlapping neighbouring tiles always add up the same way
+    pub fn render(&self, film: &mut Film, plan: &[u64]) {
+        let tiles = film.tiles(self.settings.tile_size, plan);
+        let tile_count = tiles.len();
+        let finished = AtomicUsize::new(0);
+
+        let tiles: Vec<Tile> = tiles
+            .into_par_iter()
+            .map(|mut tile| {
+                self.render_tile(&mut tile);
+
+                let finished = finished.fetch_add(1, Ordering::Relaxed) + 1;
+                eprint!("\rTiles rendered: {finished}/{tile_count}");
+                tile
+            })
+            .collect();
+        eprintln!();
+
+        for tile in &tiles {
+            film.merge(tile);
+        }
+    }
+
+    // Render in passes of pass_samples samples per pixel, taking a film which already has
+    // rendered samples per pixel up to the target in the settings, or until the time limit.
+    // after_pass is called with the film, its samples per pixel & whether it's finished
+    // once each pass is merged
+    pub fn render_progressive<E>(
+        &self,
+        film: &mut Film,
+        rendered: u64,
+        pass_samples: u64,
+        mut after_pass: impl FnMut(&Film, u64, bool) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let samples_per_pixel = match self.settings.time_limit {
+            Some(_) => u64::MAX,
+            None => self.settings.samples_per_pixel,
+        };
+        let pass_samples = pass_samples.max(1);
+        let mut rendered = rendered;
+        let mut timer = PassTimer::new(self.settings.time_limit);
+
+        while rendered < samples_per_pixel {
+            let samples = pass_samples.min(samples_per_pixel - rendered);
+            timer.start_pass();
+            self.render(film, &vec![samples; film.pixel_count()]);
+            timer.end_pass();
+            rendered += samples;
+
+            match self.settings.time_limit {
+                Some(_) => eprintln!("Samples per pixel: {rendered}"),
+                None => eprintln!("Samples per pixel: {rendered}/{samples_per_pixel}"),
+            }
+
+            let out_of_time = timer.out_of_time();
+            if out_of_time {
+                eprintln!("Stopping at the time limit");
+            }
+            let finished = out_of_time || rendered >= samples_per_pixel;
+            after_pass(film, rendered, finished)?;
+            if finished {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Spend the settings' samples per pixel, summed over the image, where the film is noisiest.
+    // Every pixel gets min_samples, then pixels keep getting up to pass_samples more per pass
+    // until their relative error drops below the threshold or they reach max_samples.
+    // With a time limit there's no overall budget, only the limit.
+    // after_pass is called like in render_progressive, with the mean samples per pixel
+    pub fn render_adaptive<E>(
+        &self,
+        film: &mut Film,
+        adaptive: &AdaptiveSettings,
+        pass_samples: u64,
+        mut after_pass: impl FnMut(&Film, u64, bool) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let pixel_count = film.pixel_count() as u64;
+        let budget = match self.settings.time_limit {
+            Some(_) => u64::MAX,
+            None => self.settings.samples_per_pixel * pixel_count,
+        };
+        let pass_samples = pass_samples.max(1);
+        let mut timer = PassTimer::new(self.settings.time_limit);
+
+        // Only empty before the first pass if there was nothing left to do to begin with
+        let mut plan = plan_adaptive_pass(film, adaptive, pass_samples, budget);
+        if plan.iter().all(|&samples| samples == 0) {
+            return after_pass(film, film.total_samples() / pixel_count, true);
+        }
+
+        loop {
+            timer.start_pass();
+            self.render(film, &plan);
+            timer.end_pass();
+
+            let active = plan.iter().filter(|&&samples| samples > 0).count();
+            let rendered = film.total_samples() / pixel_count;
+            eprintln!(
+                "Samples per pixel: {rendered}/{}, active pixels: {active}/{pixel_count}",
+                self.settings.samples_per_pixel
+            );
+
+            plan = plan_adaptive_pass(film, adaptive, pass_samples, budget);
+            let out_of_time = timer.out_of_time();
+            if out_of_time {
+                eprintln!("Stopping at the time limit");
+            }
+            let finished = out_of_time || plan.iter().all(|&samples| samples == 0);
+            after_pass(film, rendered, finished)?;
+            if finished {
+                return Ok(());
+            }
+        }
+    }
+
+    fn render_tile(&self, tile: &mut Tile) {
+        let start_counts = stats::counts();
+        let mut sampler = self
+            .settings
+            .sampler
+            .build(self.settings.seed, self.settings.samples_per_pixel);
+        let environment = Environment {
+            background: self.background,
+            world: &self.world,
+            lights: &self.lights,
+            material_ids: &self.material_ids,
+        };
+
+        for (x, y, samples) in tile.pixels() {
+            // Film rows run top to bottom, but the camera's v runs bottom to top
+            let j = self.height - 1 - y;
+
+            for sample in samples {
+                sampler.start_sample(x, y, sample);
+                stats::count_camera_ray();
+
+                let (du, dv) = sampler.get_2d();
+                // Pixels cover the image edge to edge, which works for a single pixel too
+                let u = (x as f64 + du) / self.width as f64;
+                let v = (j as f64 + dv) / self.height as f64;
+                let ray = self.camera.get_ray(u, v, sampler.as_mut());
+
+                let mut aovs = AovSample::default();
+                let color =
+                    self.integrator
+                        .radiance(&ray, &environment, sampler.as_mut(), &mut aovs);
+                // The film's y runs the opposite way to dv
+                tile.add_sample(x, y, (du, 1.0 - dv), color, &aovs);
+            }
+        }
+
+        *self.counts.lock().unwrap() += stats::counts() - start_counts;
+    }
+}
+
+// How many samples each pixel gets in the next adaptive pass, all zero once every pixel has
+// converged or the budget of samples summed over the image is spent. Dropped samples count
+// towards the limits, so pixels which only give NaNs still finish
+fn plan_adaptive_pass(
+    film: &Film,
+    adaptive: &AdaptiveSettings,
+    pass_samples: u64,
+    budget: u64,
+) -> Vec<u64> {
+    let max_samples = adaptive.max_samples.max(adaptive.min_samples);
+    let taken = film.taken_counts();
+    let warming_up = taken.iter().any(|&count| count < adaptive.min_samples);
+    let mut plan: Vec<u64> = taken
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| {
+            if warming_up {
+                adaptive.min_samples.saturating_sub(count).min(pass_samples)
+            } else if count < max_samples && film.relative_error(i) > adaptive.threshold {
+                (max_samples - count).min(pass_samples)
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    // Warming up is always finished, otherwise scale the pass down to what's left
+    let planned: u64 = plan.iter().sum();
+    let remaining = budget.saturating_sub(taken.iter().sum());
+    if !warming_up && planned > remaining {
+        for samples in plan.iter_mut() {
+            *samples = *samples * remaining / planned;
+        }
+    }
+
+    plan
+}
+
+// Tracks whether there's time for another pass, assuming it takes as long as the last one
+struct PassTimer {
+    deadline: Option<Instant>,
+    pass_start: Instant,
+    last_pass: Option<Duration>,
+}
+
+impl PassTimer {
+    fn new(time_limit: Option<Duration>) -> Self {
+        let now = Instant::now();
+
+        Self {
+            deadline: time_limit.map(|limit| now + limit),
+            pass_start: now,
+            last_pass: None,
+        }
+    }
+
+    fn start_pass(&mut self) {
+        self.pass_start = Instant::now();
+    }
+
+    fn end_pass(&mut self) {
+        self.last_pass = Some(self.pass_start.elapsed());
+    }
+
+    // The first pass always runs, so there's always an image
+    fn out_of_time(&self) -> bool {
+        match (self.deadline, self.last_pass) {
+            (Some(deadline), Some(last_pass)) => Instant::now() + last_pass > deadline,
+            _ => false,
+        }
+    }
+}
+
+// Settings for tests to override what they care about
+#[cfg(test)]
+pub fn test_settings() -> RenderSettings {
+    RenderSettings {
+        integrator: IntegratorKind::Path,
+        min_depth: 3,
+        max_depth: 10,
+        tile_size: 8,
+        seed: 0,
+        sampler: SamplerKind::Sobol,
+        light_sampling: LightSampling::Mis,
+        clamp: Default::default(),
+        filter: Filter::default(),
+        samples_per_pixel: 4,
+        time_limit: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rayon::ThreadPoolBuilder;
+
+    use crate::{
+        film::{Aov, AovSample, Film},
+        filter::{Filter, FilterKind},
+        integrator::LightSampling,
+        math::Color,
+        scene::build_scene,
+        tonemap::luminance,
+    };
+
+    use super::{plan_adaptive_pass, test_settings, AdaptiveSettings, RenderSettings, Renderer};
+
+    fn render_with_threads(
+        threads: usize,
+        tile_size: usize,
+        seed: u64,
+        filter: Filter,
+    ) -> Vec<f64> {
+        let mut scene = build_scene("weekend_scene", &[2], seed).unwrap();
+        scene.image.resize(Some(24), Some(16));
+        let renderer = Renderer::new(
+            scene,
+            RenderSettings {
+                tile_size,
+                seed,
+                filter,
+                ..test_settings()
+            },
+        );
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut film = renderer.film(false);
+        pool.install(|| renderer.render(&mut film, &[4; 24 * 16]));
+
+        film.resolve()
+            .pixels()
+            .iter()
+            .flat_map(|pixel| [pixel.x, pixel.y, pixel.z])
+            .collect()
+    }
+
+    #[test]
+    pub fn same_seed_same_image() {
+        // Compare bits so NaNs would count as equal too
+        let same_bits = |a: Vec<f64>, b: Vec<f64>| {
+            a.iter()
+                .zip(b.iter())
+                .all(|(a, b)| a.to_bits() == b.to_bits())
+        };
+
+        let single = render_with_threads(1, 16, 42, Filter::default());
+        let multi = render_with_threads(4, 5, 42, Filter::default());
+        assert!(same_bits(single, multi));
+
+        // Splats crossing tiles are merged in the same order whatever the thread count
+        let mitchell = Filter::new(FilterKind::Mitchell, None);
+        let single = render_with_threads(1, 5, 42, mitchell);
+        let multi = render_with_threads(4, 5, 42, mitchell);
+        assert!(same_bits(single, multi));
+    }
+
+    #[test]
+    pub fn different_seed_different_image() {
+        assert_ne!(
+            render_with_threads(2, 8, 1, Filter::default()),
+            render_with_threads(2, 8, 2, Filter::default())
+        );
+    }
+
+    #[test]
+    pub fn adaptive_respects_limits_and_budget() {
+        let mut scene = build_scene("cornell_box", &[], 0).unwrap();
+        scene.image.resize(Some(16), Some(16));
+        let renderer = Renderer::new(
+            scene,
+            RenderSettings {
+                samples_per_pixel: 16,
+                ..test_settings()
+            },
+        );
+        let adaptive = AdaptiveSettings {
+            min_samples: 4,
+            max_samples: 64,
+            threshold: 0.05,
+        };
+
+        let mut film = renderer.film(false);
+        let mut passes = vec![];
+        renderer
+            .render_adaptive(&mut film, &adaptive, 4, |film, _, finished| {
+                passes.push((film.total_samples(), finished));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+
+        // Each pass is reported once, & only the last is finished
+        let counts = film.sample_counts();
+        assert!(passes.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(Some(&(film.total_samples(), true)), passes.last());
+        assert_eq!(1, passes.iter().filter(|(_, finished)| *finished).count());
+        assert!(film.total_samples() <= 16 * 16 * 16);
+        assert!(counts.iter().all(|&count| (4..=64).contains(&count)));
+        // The noise isn't even, so neither are the samples
+        assert!(counts.contains(&4) && counts.iter().any(|&count| count > 4));
+    }
+
+    #[test]
+    pub fn adaptive_finishes_when_every_sample_is_dropped() {
+        let mut film = Film::new(2, 1, Filter::default());
+        let adaptive = AdaptiveSettings {
+            min_samples: 2,
+            max_samples: 8,
+            threshold: 0.05,
+        };
+
+        let mut passes = 0;
+        loop {
+            let plan = plan_adaptive_pass(&film, &adaptive, 4, u64::MAX);
+            if plan.iter().all(|&samples| samples == 0) {
+                break;
+            }
+            for mut tile in film.tiles(2, &plan) {
+                for (x, y, samples) in tile.pixels() {
+                    for _ in samples {
+                        let color = Color::new(f64::NAN, 0.0, 0.0);
+                        tile.add_sample(x, y, (0.5, 0.5), color, &AovSample::default());
+                    }
+                }
+                film.merge(&tile);
+            }
+            passes += 1;
+            assert!(passes <= 3, "still sampling after {passes} passes");
+        }
+
+        assert_eq!(vec![8, 8], film.taken_counts());
+    }
+
+    fn mean_brightness(scene: &str, light_sampling: LightSampling, min_depth: i64) -> f64 {
+        let mut scene = build_scene(scene, &[], 0).unwrap();
+        scene.image.resize(Some(8), Some(8));
+        let renderer = Renderer::new(
+            scene,
+            RenderSettings {
+                min_depth,
+                light_sampling,
+                samples_per_pixel: 256,
+                ..test_settings()
+            },
+        );
+
+        let mut film = renderer.film(false);
+        renderer.render(&mut film, &[256; 8 * 8]);
+
+        let image = film.resolve();
+        image
+            .pixels()
+            .iter()
+            .map(|&pixel| luminance(pixel))
+            .sum::<f64>()
+            / 64.0
+    }
+
+    #[test]
+    pub fn light_sampling_converges_to_the_same_image() {
+        for scene in ["cornell_box", "lights"] {
+            let unsampled = mean_brightness(scene, LightSampling::Off, 3);
+
+            for light_sampling in [LightSampling::Mis, LightSampling::Mixture] {
+                let sampled = mean_brightness(scene, light_sampling, 3);
+                assert!(
+                    (sampled - unsampled).abs() < 0.05 * unsampled,
+                    "{scene} {light_sampling:?}: {sampled} {unsampled}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn russian_roulette_converges_to_the_same_image() {
+        let full_depth = mean_brightness("cornell_box", LightSampling::Mis, 10);
+        let roulette = mean_brightness("cornell_box", LightSampling::Mis, 1);
+
+        assert!(
+            (roulette - full_depth).abs() < 0.05 * full_depth,
+            "{roulette} {full_depth}"
+        );
+    }
+
+    #[test]
+    pub fn scenes_with_empty_groups_render() {
+        // No floor boxes & no spheres in the box, which would be empty BVHs
+        let mut scene = build_scene("week_scene", &[0, 0], 0).unwrap();
+        scene.image.resize(Some(4), Some(4));
+        let renderer = Renderer::new(scene, test_settings());
+
+        let mut film = renderer.film(false);
+        renderer.render(&mut film, &[1; 4 * 4]);
+
+        assert_eq!(vec![1; 4 * 4], film.sample_counts());
+    }
+
+    #[test]
+    pub fn single_pixel_images_render() {
+        let mut scene = build_scene("cornell_box", &[], 0).unwrap();
+        scene.image.resize(Some(1), Some(1));
+        let renderer = Renderer::new(scene, test_settings());
+
+        let mut film = renderer.film(false);
+        renderer.render(&mut film, &[16]);
+
+        assert_eq!(vec![16], film.sample_counts());
+        assert!(luminance(film.resolve().get(0, 0)) > 0.0);
+    }
+
+    #[test]
+    pub fn light_aovs_add_up_to_the_image() {
+        let mut scene = build_scene("cornell_box", &[], 0).unwrap();
+        scene.image.resize(Some(8), Some(8));
+        let renderer = Renderer::new(scene, test_settings());
+
+        let mut film = renderer.film(true);
+        renderer.render(&mut film, &[4; 8 * 8]);
+
+        let image = film.resolve();
+        let emission = film.resolve_aov(Aov::Emission).unwrap();
+        let direct = film.resolve_aov(Aov::Direct).unwrap();
+        let indirect = film.resolve_aov(Aov::Indirect).unwrap();
+        for i in 0..image.pixels().len() {
+            let sum = emission.pixels()[i] + direct.pixels()[i] + indirect.pixels()[i];
+            assert!((sum - image.pixels()[i]).norm() < 1e-9);
+        }
+        assert!(direct.pixels().iter().any(|pixel| pixel.max() > 0.0));
+    }
+
+    #[test]
+    pub fn stats_count_the_work() {
+        let mut scene = build_scene("cornell_box", &[], 0).unwrap();
+        scene.image.resize(Some(8), Some(8));
+        let renderer = Renderer::new(
+            scene,
+            RenderSettings {
+                tile_size: 4,
+                ..test_settings()
+            },
+        );
+
+        let mut film = renderer.film(false);
+        renderer.render(&mut film, &[4; 8 * 8]);
+        let stats = renderer.stats(Duration::from_secs(1));
+
+        assert_eq!(4 * 8 * 8, stats.camera_rays);
+        assert!(stats.secondary_rays > 0);
+        assert!(stats.mean_path_length >= 1.0);
+        assert!(stats.primitive_tests_per_ray > 0.0);
+        assert!(stats.aabb_tests_per_ray > 0.0);
+    }
+
+    #[test]
+    pub fn time_limit_only_stops_between_passes() {
+        let mut scene = build_scene("cornell_box", &[], 0).unwrap();
+        scene.image.resize(Some(8), Some(8));
+        let renderer = Renderer::new(
+            scene,
+            RenderSettings {
+                tile_size: 4,
+                time_limit: Some(Duration::from_nanos(1)),
+                ..test_settings()
+            },
+        );
+
+        // Out of time after the first pass, but that pass is always finished & only reported once
+        let mut film = renderer.film(false);
+        let mut passes = vec![];
+        renderer
+            .render_progressive(&mut film, 0, 2, |film, rendered, finished| {
+                passes.push((rendered, finished));
+                assert!(film.sample_counts().iter().all(|&count| count == rendered));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+
+        assert_eq!(vec![(2, true)], passes);
+    }
+}
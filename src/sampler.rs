@@ -0,0 +1,502 @@
+use clap::ValueEnum;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::math::{sample_rng, RenderRng};
+
+// Hands out sample values in [0, 1) for each dimension of a pixel sample, e.g. the pixel position,
+// the lens position, the shutter time & then the scatter direction at every bounce.
+// Asking for dimensions in the same order for every sample lets well distributed samplers
+// spread each dimension evenly across a pixel's samples
+pub trait Sampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u64);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+
+    // An independent generator for the current sample, for anything which needs an unknown
+    // amount of random numbers & would throw the dimensions out of step
+    fn rng(&mut self) -> &mut RenderRng;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum SamplerKind {
+    /// Uniform random numbers
+    Independent,
+    /// Jittered samples in a randomly permuted grid of strata
+    Stratified,
+    /// The Halton sequence with a random shift per pixel
+    Halton,
+    /// The Sobol sequence with Owen scrambling
+    Sobol,
+}
+
+impl SamplerKind {
+    // samples_per_pixel is only used by the stratified sampler to size its strata
+    pub fn build(self, seed: u64, samples_per_pixel: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+// The per sample state shared by every sampler
+struct SampleState {
+    seed: u64,
+    // Mixes the seed & pixel position, for decorrelating pixels from each other
+    pixel_hash: u64,
+    index: u64,
+    dimension: u64,
+    rng: RenderRng,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_hash: 0,
+            index: 0,
+            dimension: 0,
+            rng: sample_rng(seed, 0, 0, 0),
+        }
+    }
+
+    fn start(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel_hash = hash(&[self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = sample_rng(self.seed, x, y, index);
+    }
+
+    // Claim the next dimension, returning a hash unique to it & the pixel
+    fn next_dimension(&mut self) -> u64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        hash(&[self.pixel_hash, dimension])
+    }
+}
+
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.state.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.state.rng.gen(), self.state.rng.gen())
+    }
+
+    fn rng(&mut self) -> &mut RenderRng {
+        &mut self.state.rng
+    }
+}
+
+pub struct StratifiedSampler {
+    state: SampleState,
+    strata_1d: u64,
+    // Strata per side of the 2D grid
+    strata_2d: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u64) -> Self {
+        let samples_per_pixel = samples_per_pixel.max(1);
+
+        Self {
+            state: SampleState::new(seed),
+            strata_1d: samples_per_pixel,
+            strata_2d: (samples_per_pixel as f64).sqrt().ceil() as u64,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension_hash = self.state.next_dimension();
+
+        // Each pass through the strata gets its own permutation
+        let n = self.strata_1d;
+        let pass = hash(&[dimension_hash, self.state.index / n]);
+        let stratum = permute(self.state.index % n, n, pass);
+
+        (stratum as f64 + self.state.rng.gen::<f64>()) / n as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension_hash = self.state.next_dimension();
+
+        let side = self.strata_2d;
+        let n = side * side;
+        let pass = hash(&[dimension_hash, self.state.index / n]);
+        let stratum = permute(self.state.index % n, n, pass);
+
+        (
+            ((stratum % side) as f64 + self.state.rng.gen::<f64>()) / side as f64,
+            ((stratum / side) as f64 + self.state.rng.gen::<f64>()) / side as f64,
+        )
+    }
+
+    fn rng(&mut self) -> &mut RenderRng {
+        &mut self.state.rng
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: SampleState::new(seed),
+        }
+    }
+
+    fn sample_dimension(&mut self) -> f64 {
+        let dimension = self.state.dimension as usize;
+        let dimension_hash = self.state.next_dimension();
+
+        // Beyond the prime table the bases repeat, the random shift keeps them decorrelated
+        let base = PRIMES[dimension % PRIMES.len()];
+        let shift = to_unit(dimension_hash);
+
+        (radical_inverse(self.state.index, base) + shift).fract()
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.sample_dimension()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.sample_dimension(), self.sample_dimension())
+    }
+
+    fn rng(&mut self) -> &mut RenderRng {
+        &mut self.state.rng
+    }
+}
+
+// Every 1D or 2D request draws from the first two Sobol dimensions with its own shuffle &
+// scramble (Burley 2020, Practical Hash-based Owen Scrambling), so any number of dimensions
+// can be used without the correlation problems of higher Sobol dimensions
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: SampleState::new(seed),
+        }
+    }
+
+    fn sample(&mut self, dimensions: usize) -> [f64; 2] {
+        let dimension_hash = self.state.next_dimension();
+
+        // Sample indices past 2^32 wrap around, that's plenty of samples for a pixel
+        let index = nested_uniform_scramble(self.state.index as u32, dimension_hash as u32);
+
+        let mut result = [0.0; 2];
+        for (d, value) in result.iter_mut().enumerate().take(dimensions) {
+            let seed = hash(&[dimension_hash, d as u64 + 1]) as u32;
+            let scrambled = nested_uniform_scramble(sobol(index, d), seed);
+
+            // Keep 24 bits so the result is always below 1 in floating point
+            *value = (scrambled >> 8) as f64 / (1 << 24) as f64;
+        }
+
+        result
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.sample(1)[0]
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let [u, v] = self.sample(2);
+
+        (u, v)
+    }
+
+    fn rng(&mut self) -> &mut RenderRng {
+        &mut self.state.rng
+    }
+}
+
+fn radical_inverse(mut index: u64, base: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_n = 1.0;
+    let mut reversed = 0;
+
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base + digit;
+        inverse_base_n *= inverse_base;
+        index = next;
+    }
+
+    (reversed as f64 * inverse_base_n).min(1.0 - f64::EPSILON)
+}
+
+// Direction numbers for the first two Sobol dimensions.
+// The first is the van der Corput sequence, the second uses the polynomial x + 1
+const SOBOL_DIRECTIONS: [[u32; 32]; 2] = sobol_directions();
+
+const fn sobol_directions() -> [[u32; 32]; 2] {
+    let mut directions = [[0; 32]; 2];
+
+    let mut i = 0;
+    while i < 32 {
+        directions[0][i] = 1 << (31 - i);
+        i += 1;
+    }
+
+    // For degree 1, v_i = v_(i-1) ^ (v_(i-1) >> 1), starting from m_1 = 1
+    directions[1][0] = 1 << 31;
+    let mut i = 1;
+    while i < 32 {
+        directions[1][i] = directions[1][i - 1] ^ (directions[1][i - 1] >> 1);
+        i += 1;
+    }
+
+    directions
+}
+
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut result = 0;
+    let mut bit = 0;
+
+    while index != 0 {
+        if index & 1 == 1 {
+            result ^= SOBOL_DIRECTIONS[dimension][bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+
+    result
+}
+
+// An Owen scramble of the bits of x, treating them as a fraction
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+
+    x
+}
+
+// Map i to its position in a random permutation of 0..n (Kensler 2013, Correlated Multi-Jittered Sampling)
+fn permute(mut i: u64, n: u64, seed: u64) -> u64 {
+    if n <= 1 {
+        return 0;
+    }
+
+    let seed = seed as u32;
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    w |= w >> 32;
+
+    // Cycle walk until the permuted value lands inside the range
+    loop {
+        let mut x = i as u32;
+        x ^= seed;
+        x = x.wrapping_mul(0xe170893d);
+        x ^= seed >> 16;
+        x ^= (x & w as u32) >> 4;
+        x ^= seed >> 8;
+        x = x.wrapping_mul(0x0929eb3f);
+        x ^= seed >> 23;
+        x ^= (x & w as u32) >> 1;
+        x = x.wrapping_mul(1 | seed >> 27);
+        x = x.wrapping_mul(0x6935fa69);
+        x ^= (x & w as u32) >> 11;
+        x = x.wrapping_mul(0x74dcb303);
+        x ^= (x & w as u32) >> 2;
+        x = x.wrapping_mul(0x9e501cc3);
+        x ^= (x & w as u32) >> 2;
+        x = x.wrapping_mul(0xc860a3df);
+        x &= w as u32;
+        x ^= x >> 5;
+
+        i = x as u64;
+        if i < n {
+            return (i + seed as u64) % n;
+        }
+    }
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x243f6a8885a308d3, |acc, &value| {
+        let mut z = (acc ^ value).wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+        z ^ (z >> 31)
+    })
+}
+
+#[inline]
+fn to_unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{permute, SamplerKind};
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    #[test]
+    pub fn samples_in_unit_range() {
+        for kind in KINDS {
+            let mut sampler = kind.build(3, 64);
+            for index in 0..256 {
+                sampler.start_sample(5, 7, index);
+                for _ in 0..8 {
+                    let (u, v) = sampler.get_2d();
+                    let w = sampler.get_1d();
+                    assert!((0.0..1.0).contains(&u), "{kind:?} gave {u}");
+                    assert!((0.0..1.0).contains(&v), "{kind:?} gave {v}");
+                    assert!((0.0..1.0).contains(&w), "{kind:?} gave {w}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn samples_are_repeatable() {
+        for kind in KINDS {
+            let mut a = kind.build(11, 16);
+            let mut b = kind.build(11, 16);
+
+            a.start_sample(1, 2, 9);
+            a.get_2d();
+            b.start_sample(1, 2, 9);
+            b.get_2d();
+
+            assert_eq!(a.get_2d(), b.get_2d(), "{kind:?}");
+        }
+    }
+
+    #[test]
+    pub fn stratified_and_sobol_fill_every_cell() {
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            let mut sampler = kind.build(5, 16);
+
+            // Check the first dimension & a later one, at a few pixels
+            for dimension in [0, 3] {
+                for pixel in 0..4 {
+                    let mut cells = [0; 16];
+
+                    for index in 0..16 {
+                        sampler.start_sample(pixel, 0, index);
+                        for _ in 0..dimension {
+                            sampler.get_2d();
+                        }
+                        let (u, v) = sampler.get_2d();
+                        cells[(v * 4.0) as usize * 4 + (u * 4.0) as usize] += 1;
+                    }
+
+                    assert_eq!([1; 16], cells, "{kind:?} dimension {dimension}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn low_discrepancy_beats_independent() {
+        // Estimate the integral of u * v over the unit square, which is 0.25
+        let error = |kind: SamplerKind| {
+            let mut total_error = 0.0;
+            for pixel in 0..16 {
+                let mut sampler = kind.build(1, 256);
+                let mut sum = 0.0;
+                for index in 0..256 {
+                    sampler.start_sample(pixel, 0, index);
+                    let (u, v) = sampler.get_2d();
+                    sum += u * v;
+                }
+                total_error += (sum / 256.0 - 0.25_f64).abs();
+            }
+
+            total_error
+        };
+
+        let independent = error(SamplerKind::Independent);
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            assert!(error(kind) < independent, "{kind:?}");
+        }
+    }
+
+    #[test]
+    pub fn permute_is_a_permutation() {
+        for n in [1, 2, 5, 16, 100] {
+            let mut seen = vec![false; n as usize];
+            for i in 0..n {
+                seen[permute(i, n, 1234) as usize] = true;
+            }
+
+            assert!(seen.iter().all(|&s| s));
+        }
+    }
+}
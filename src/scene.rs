@@ -1,7 +1,7 @@
 #![allow(dead_code)]
-use std::{path::Path, sync::Arc};
+use std::{error::Error, fmt::Display, path::Path, sync::Arc};
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 
 use crate::{
     bvh::BVHNode,
@@ -9,8 +9,9 @@ use crate::{
     hittable::HittableList,
     instance::{Rotate, Translate},
     material::{Dielectric, DiffuseLight, Lambertian, Metal},
-    math::{random_color, random_point, random_range, Color, Point, Vector},
+    math::{random_color, random_point, random_range, Color, Point, RenderRng, Vector},
     rectangle::{Cuboid, XyRectangle, XzRectangle, YzRectangle},
+    scene_file::{self, SceneFileError},
     sphere::{MovingSphere, Sphere},
     texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColorTexture},
     volumes::ConstantVolume,
@@ -19,6 +20,8 @@ use crate::{
 
 pub struct Scene {
     pub objects: HittableList,
+    // Emitters which are sampled directly, each should also be in objects
+    pub lights: HittableList,
     pub background: Color,
     pub camera: Camera,
     pub image: Image,
@@ -38,9 +41,154 @@ impl Image {
             samples_per_pixel,
         }
     }
+
+    // If only one dimension is overridden, the other follows the current aspect ratio, but is
+    // never less than a pixel
+    pub fn resize(&mut self, width: Option<i64>, height: Option<i64>) {
+        let aspect_ratio = self.width as f64 / self.height as f64;
+
+        match (width, height) {
+            (Some(width), Some(height)) => {
+                self.width = width;
+                self.height = height;
+            }
+            (Some(width), None) => {
+                self.width = width;
+                self.height = ((width as f64 / aspect_ratio) as i64).max(1);
+            }
+            (None, Some(height)) => {
+                self.width = ((height as f64 * aspect_ratio) as i64).max(1);
+                self.height = height;
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+pub struct SceneEntry {
+    pub name: &'static str,
+    // Each parameter's name & the default used when it isn't passed
+    pub params: &'static [(&'static str, i64)],
+    build: fn(&[i64], &mut RenderRng) -> Scene,
+}
+
+pub const SCENES: &[SceneEntry] = &[
+    SceneEntry {
+        name: "weekend_scene",
+        params: &[("n", 11)],
+        build: |p, rng| weekend_scene(p[0], rng),
+    },
+    SceneEntry {
+        name: "week_scene",
+        params: &[("floor_n", 20), ("sphere_n", 1000)],
+        build: |p, rng| week_scene(p[0], p[1], rng),
+    },
+    SceneEntry {
+        name: "two_spheres",
+        params: &[],
+        build: |_, _| two_spheres(),
+    },
+    SceneEntry {
+        name: "two_perlin_spheres",
+        params: &[],
+        build: |_, rng| two_perlin_spheres(rng),
+    },
+    SceneEntry {
+        name: "earth",
+        params: &[],
+        build: |_, _| earth(),
+    },
+    SceneEntry {
+        name: "lights",
+        params: &[],
+        build: |_, rng| lights(rng),
+    },
+    SceneEntry {
+        name: "cornell_box",
+        params: &[],
+        build: |_, _| cornell_box(),
+    },
+    SceneEntry {
+        name: "cornell_box_smoke",
+        params: &[],
+        build: |_, _| cornell_box_smoke(),
+    },
+];
+
+#[derive(Debug)]
+pub enum SceneError {
+    UnknownScene(String),
+    TooManyParams {
+        scene: &'static str,
+        expected: usize,
+        got: usize,
+    },
+    FileParams,
+    File(SceneFileError),
 }
 
-pub fn weekend_scene(n: i64) -> Scene {
+impl Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::UnknownScene(name) => write!(f, "unknown scene '{name}'"),
+            SceneError::TooManyParams {
+                scene,
+                expected,
+                got,
+            } => write!(
+                f,
+                "{scene} takes {expected} parameters but {got} were given"
+            ),
+            SceneError::FileParams => write!(f, "scene files don't take parameters"),
+            SceneError::File(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl From<SceneFileError> for SceneError {
+    fn from(err: SceneFileError) -> Self {
+        SceneError::File(err)
+    }
+}
+
+// Build a scene by name, missing parameters take the scene's defaults. Names ending in .toml
+// are loaded from that scene file instead.
+// Random scenes are generated from the seed, so the same seed always gives the same scene
+pub fn build_scene(name: &str, params: &[i64], seed: u64) -> Result<Scene, SceneError> {
+    if name.ends_with(".toml") {
+        if !params.is_empty() {
+            return Err(SceneError::FileParams);
+        }
+
+        return Ok(scene_file::load(Path::new(name), seed)?);
+    }
+
+    let entry = SCENES
+        .iter()
+        .find(|entry| entry.name == name)
+        .ok_or_else(|| SceneError::UnknownScene(name.to_string()))?;
+
+    if params.len() > entry.params.len() {
+        return Err(SceneError::TooManyParams {
+            scene: entry.name,
+            expected: entry.params.len(),
+            got: params.len(),
+        });
+    }
+
+    let params: Vec<i64> = entry
+        .params
+        .iter()
+        .enumerate()
+        .map(|(i, (_, default))| params.get(i).copied().unwrap_or(*default))
+        .collect();
+
+    Ok((entry.build)(&params, &mut RenderRng::seed_from_u64(seed)))
+}
+
+pub fn weekend_scene(n: i64, rng: &mut RenderRng) -> Scene {
     let mut world = HittableList::new();
 
     let checker = Arc::new(CheckerTexture::new_from_colors(
@@ -56,11 +204,11 @@ pub fn weekend_scene(n: i64) -> Scene {
 
     for a in -n..n {
         for b in -n..n {
-            let choose_material: f64 = rand::random();
+            let choose_material: f64 = rng.gen();
             let center = Point::new(
-                a as f64 + 0.9 * rand::random::<f64>(),
+                a as f64 + 0.9 * rng.gen::<f64>(),
                 0.2,
-                b as f64 + 0.9 * rand::random::<f64>(),
+                b as f64 + 0.9 * rng.gen::<f64>(),
             );
 
             if (center - Point::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
@@ -68,8 +216,8 @@ pub fn weekend_scene(n: i64) -> Scene {
                     x if x < 0.2 => {
                         // Diffuse checker
                         let checker = CheckerTexture::new(
-                            Box::new(SolidColorTexture::new(random_color(0.0, 1.0))),
-                            Box::new(SolidColorTexture::new(random_color(0.0, 1.0))),
+                            Box::new(SolidColorTexture::new(random_color(rng, 0.0, 1.0))),
+                            Box::new(SolidColorTexture::new(random_color(rng, 0.0, 1.0))),
                         );
 
                         world.add(Arc::new(Sphere::new(
@@ -83,20 +231,20 @@ pub fn weekend_scene(n: i64) -> Scene {
                         world.add(Arc::new(Sphere::new(
                             center,
                             0.2,
-                            Arc::new(Lambertian::new(Arc::new(NoiseTexture::new(10.0)))),
+                            Arc::new(Lambertian::new(Arc::new(NoiseTexture::new(10.0, rng)))),
                         )));
                     }
                     x if x < 0.8 => {
                         // Diffuse
                         let end_center: nalgebra::OPoint<f64, nalgebra::Const<3>> =
-                            center + Vector::new(0.0, random_range(0.0, 0.5), 0.0);
+                            center + Vector::new(0.0, random_range(rng, 0.0, 0.5), 0.0);
                         world.add(Arc::new(MovingSphere::new(
                             center,
                             end_center,
                             0.0,
                             1.0,
                             0.2,
-                            Arc::new(Lambertian::new_from_color(random_color(0.0, 1.0))),
+                            Arc::new(Lambertian::new_from_color(random_color(rng, 0.0, 1.0))),
                         )))
                     }
                     x if x < 0.95 => {
@@ -104,7 +252,10 @@ pub fn weekend_scene(n: i64) -> Scene {
                         world.add(Arc::new(Sphere::new(
                             center,
                             0.2,
-                            Arc::new(Metal::new(random_color(0.5, 1.0), random_range(0.0, 0.5))),
+                            Arc::new(Metal::new(
+                                random_color(rng, 0.5, 1.0),
+                                random_range(rng, 0.0, 0.5),
+                            )),
                         )))
                     }
                     _ => {
@@ -137,7 +288,7 @@ pub fn weekend_scene(n: i64) -> Scene {
             world.add(Arc::new(Sphere::new(
                 Point::new(-8.0, 1.0, 0.0),
                 1.0,
-                Arc::new(Lambertian::new(Arc::new(NoiseTexture::new(4.0)))),
+                Arc::new(Lambertian::new(Arc::new(NoiseTexture::new(4.0, rng)))),
             )))
         }
     }
@@ -159,12 +310,13 @@ pub fn weekend_scene(n: i64) -> Scene {
     Scene {
         objects: world,
         camera,
+        lights: HittableList::new(),
         background: Color::new(0.7, 0.8, 1.00),
         image,
     }
 }
 
-pub fn week_scene(floor_n: i64, sphere_n: i64) -> Scene {
+pub fn week_scene(floor_n: i64, sphere_n: i64, rng: &mut RenderRng) -> Scene {
     let mut floor = HittableList::new();
     let ground = Arc::new(Lambertian::new_from_color(Color::new(0.48, 0.83, 0.53)));
 
@@ -175,7 +327,7 @@ pub fn week_scene(floor_n: i64, sphere_n: i64) -> Scene {
             let z0 = -1000.0 + j as f64 * w;
             let y0 = 0.0;
             let x1 = x0 + w;
-            let y1 = rand::thread_rng().gen_range(1.0..101.0);
+            let y1 = rng.gen_range(1.0..101.0);
             let z1 = z0 + w;
 
             floor.add(Arc::new(Cuboid::new(
@@ -188,12 +340,15 @@ pub fn week_scene(floor_n: i64, sphere_n: i64) -> Scene {
 
     let mut objects = HittableList::new();
 
-    objects.add(Arc::new(BVHNode::new(floor, 0.0, 1.0)));
+    if let Some(floor) = BVHNode::new(floor, 0.0, 1.0, rng) {
+        objects.add(Arc::new(floor));
+    }
 
     let light = Arc::new(DiffuseLight::new_from_color(Color::new(7.0, 7.0, 7.0)));
-    objects.add(Arc::new(XzRectangle::new(
-        123.0, 423.0, 147.0, 412.0, 554.0, light,
-    )));
+    let light = Arc::new(XzRectangle::new(123.0, 423.0, 147.0, 412.0, 554.0, light));
+    let mut lights = HittableList::new();
+    lights.add(light.clone());
+    objects.add(light);
 
     let center1 = Point::new(400.0, 400.0, 200.0);
     let center2 = center1 + Vector::new(30.0, 0.0, 0.0);
@@ -248,7 +403,7 @@ pub fn week_scene(floor_n: i64, sphere_n: i64) -> Scene {
         100.0,
         earth,
     )));
-    let perlin = Arc::new(NoiseTexture::new(2.0));
+    let perlin = Arc::new(NoiseTexture::new(2.0, rng));
     objects.add(Arc::new(Sphere::new(
         Point::new(200.0, 280.0, 300.0),
         80.0,
@@ -259,19 +414,18 @@ pub fn week_scene(floor_n: i64, sphere_n: i64) -> Scene {
     let white = Arc::new(Lambertian::new_from_color(Color::new(0.73, 0.73, 0.73)));
     for _ in 0..sphere_n {
         box_spheres.add(Arc::new(Sphere::new(
-            random_point(0.0..165.0),
+            random_point(rng, 0.0..165.0),
             10.0,
             white.clone(),
         )));
     }
 
-    objects.add(Arc::new(Translate::new(
-        Box::new(Rotate::new(
-            Box::new(BVHNode::new(box_spheres, 0.0, 1.0)),
-            15.0,
-        )),
-        Vector::new(-100.0, 270.0, 395.0),
-    )));
+    if let Some(box_spheres) = BVHNode::new(box_spheres, 0.0, 1.0, rng) {
+        objects.add(Arc::new(Translate::new(
+            Box::new(Rotate::new(Box::new(box_spheres), 15.0)),
+            Vector::new(-100.0, 270.0, 395.0),
+        )));
+    }
 
     let image = Image::new(800, 10_000, 1.0);
 
@@ -291,6 +445,7 @@ pub fn week_scene(floor_n: i64, sphere_n: i64) -> Scene {
         objects,
         background: Color::new(0.0, 0.0, 0.0),
         camera,
+        lights,
         image,
     }
 }
@@ -331,15 +486,16 @@ pub fn two_spheres() -> Scene {
     Scene {
         objects: world,
         camera,
+        lights: HittableList::new(),
         background: Color::new(0.7, 0.8, 1.00),
         image,
     }
 }
 
-pub fn two_perlin_spheres() -> Scene {
+pub fn two_perlin_spheres(rng: &mut RenderRng) -> Scene {
     let mut world = HittableList::new();
 
-    let perlin = Arc::new(NoiseTexture::new(4.0));
+    let perlin = Arc::new(NoiseTexture::new(4.0, rng));
 
     world.add(Arc::new(Sphere::new(
         Point::new(0.0, -1000.0, 0.0),
@@ -369,6 +525,7 @@ pub fn two_perlin_spheres() -> Scene {
     Scene {
         objects: world,
         camera,
+        lights: HittableList::new(),
         background: Color::new(0.7, 0.8, 1.00),
         image,
     }
@@ -402,15 +559,16 @@ pub fn earth() -> Scene {
     Scene {
         objects: world,
         camera,
+        lights: HittableList::new(),
         background: Color::new(0.7, 0.8, 1.00),
         image,
     }
 }
 
-pub fn lights() -> Scene {
+pub fn lights(rng: &mut RenderRng) -> Scene {
     let mut world = HittableList::new();
 
-    let noise = Arc::new(NoiseTexture::new(4.0));
+    let noise = Arc::new(NoiseTexture::new(4.0, rng));
     world.add(Arc::new(Sphere::new(
         Point::new(0.0, -1000.0, 0.0),
         1000.0,
@@ -423,19 +581,13 @@ pub fn lights() -> Scene {
     )));
 
     let light = Arc::new(DiffuseLight::new_from_color(Color::new(4.0, 4.0, 4.0)));
-    world.add(Arc::new(Sphere::new(
-        Point::new(0.0, 8.0, 0.0),
-        2.0,
-        light.clone(),
-    )));
-    world.add(Arc::new(XyRectangle::new(
-        3.0,
-        5.0,
-        1.0,
-        3.0,
-        -2.0,
-        light.clone(),
-    )));
+    let sphere_light = Arc::new(Sphere::new(Point::new(0.0, 8.0, 0.0), 2.0, light.clone()));
+    let rectangle_light = Arc::new(XyRectangle::new(3.0, 5.0, 1.0, 3.0, -2.0, light.clone()));
+    let mut lights = HittableList::new();
+    lights.add(sphere_light.clone());
+    lights.add(rectangle_light.clone());
+    world.add(sphere_light);
+    world.add(rectangle_light);
 
     let camera = Camera::new(
         Point::new(26.0, 3.0, 6.0),
@@ -455,6 +607,7 @@ pub fn lights() -> Scene {
         objects: world,
         background: Color::new(0.0, 0.0, 0.0),
         camera,
+        lights,
         image,
     }
 }
@@ -471,9 +624,10 @@ pub fn cornell_box() -> Scene {
         0.0, 555.0, 0.0, 555.0, 555.0, green,
     )));
     objects.add(Arc::new(YzRectangle::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Arc::new(XzRectangle::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    )));
+    let light = Arc::new(XzRectangle::new(213.0, 343.0, 227.0, 332.0, 554.0, light));
+    let mut lights = HittableList::new();
+    lights.add(light.clone());
+    objects.add(light);
     objects.add(Arc::new(XzRectangle::new(
         0.0,
         555.0,
@@ -535,6 +689,7 @@ pub fn cornell_box() -> Scene {
         objects,
         background: Color::new(0.0, 0.0, 0.0),
         camera,
+        lights,
         image,
     }
 }
@@ -551,9 +706,10 @@ pub fn cornell_box_smoke() -> Scene {
         0.0, 555.0, 0.0, 555.0, 555.0, green,
     )));
     objects.add(Arc::new(YzRectangle::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Arc::new(XzRectangle::new(
-        113.0, 443.0, 127.0, 432.0, 554.0, light,
-    )));
+    let light = Arc::new(XzRectangle::new(113.0, 443.0, 127.0, 432.0, 554.0, light));
+    let mut lights = HittableList::new();
+    lights.add(light.clone());
+    objects.add(light);
     objects.add(Arc::new(XzRectangle::new(
         0.0,
         555.0,
@@ -623,6 +779,7 @@ pub fn cornell_box_smoke() -> Scene {
         objects,
         background: Color::new(0.0, 0.0, 0.0),
         camera,
+        lights,
         image,
     }
 }
@@ -0,0 +1,1010 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    fs, io,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use image::ImageError;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::{
+    bvh::BVHNode,
+    camera::Camera,
+    hittable::{Hittable, HittableList},
+    instance::{Rotate, Translate},
+    material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal},
+    math::{Color, Point, RenderRng, Vector},
+    obj::{self, ObjError},
+    ply::{self, PlyError},
+    rectangle::{Cuboid, XyRectangle, XzRectangle, YzRectangle},
+    scene::{Image, Scene},
+    sphere::{MovingSphere, Sphere},
+    texture::{
+        CheckerTexture, ImageTexture, NoiseTexture, SolidColorTexture, Texture, VertexColorTexture,
+    },
+    triangle::{MeshBuffers, MeshError, Triangle, TriangleMesh},
+    volumes::ConstantVolume,
+};
+
+// A scene described in TOML, see scenes/cornell_box.toml for an example
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    background: Color,
+    image: ImageDescription,
+    camera: CameraDescription,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    textures: BTreeMap<String, Spanned<TextureDescription>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    materials: BTreeMap<String, Spanned<MaterialDescription>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    objects: Vec<Spanned<ObjectDescription>>,
+    // Emitters which are sampled directly, these are added to the objects too
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lights: Vec<Spanned<ObjectDescription>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImageDescription {
+    width: Spanned<i64>,
+    height: Spanned<i64>,
+    samples_per_pixel: u64,
+}
+
+// The camera's aspect ratio is the image's
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub look_from: Point,
+    pub look_at: Point,
+    #[serde(default = "default_view_up")]
+    pub view_up: Vector,
+    pub vfov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    #[serde(default = "default_focus_distance")]
+    pub focus_distance: f64,
+    #[serde(default)]
+    pub shutter_open: f64,
+    #[serde(default)]
+    pub shutter_close: f64,
+}
+
+fn default_view_up() -> Vector {
+    Vector::new(0.0, 1.0, 0.0)
+}
+
+fn default_focus_distance() -> f64 {
+    10.0
+}
+
+// Either a color, or the name of a texture
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(
+    untagged,
+    expecting = "a color like [0.5, 0.5, 0.5] or the name of a texture"
+)]
+pub enum TextureRef {
+    Color(Color),
+    Named(String),
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDescription {
+    Solid { color: Color },
+    Checker { odd: TextureRef, even: TextureRef },
+    Noise { scale: f64 },
+    // Relative to the scene file
+    Image { path: PathBuf },
+    // The colors of the vertices of the mesh it's on
+    VertexColor,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDescription {
+    Lambertian { albedo: TextureRef },
+    Metal { albedo: Color, fuzz: f64 },
+    Dielectric { refraction_index: f64 },
+    DiffuseLight { emit: TextureRef },
+    Isotropic { albedo: TextureRef },
+}
+
+// Materials are referred to by name
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDescription {
+    Sphere {
+        center: Point,
+        radius: f64,
+        material: String,
+    },
+    MovingSphere {
+        center0: Point,
+        center1: Point,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: String,
+    },
+    XyRectangle {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: String,
+    },
+    XzRectangle {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: String,
+    },
+    YzRectangle {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: String,
+    },
+    Cuboid {
+        min: Point,
+        max: Point,
+        material: String,
+    },
+    // Normals, uvs & colors are optional, one per vertex
+    Triangle {
+        vertices: [Point; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normals: Option<[Vector; 3]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        uvs: Option<[[f64; 2]; 3]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        colors: Option<[Color; 3]>,
+        material: String,
+    },
+    // Triangles given by indices into the vertex buffers. Normals, uvs & colors are optional,
+    // but if given there must be one per position
+    TriangleMesh {
+        positions: Vec<Point>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        normals: Vec<Vector>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        uvs: Vec<[f64; 2]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        colors: Vec<Color>,
+        indices: Vec<[u32; 3]>,
+        material: String,
+    },
+    Translate {
+        offset: Vector,
+        object: Box<ObjectDescription>,
+    },
+    // About the y axis, in degrees
+    Rotate {
+        angle: f64,
+        object: Box<ObjectDescription>,
+    },
+    ConstantVolume {
+        density: f64,
+        albedo: TextureRef,
+        boundary: Box<ObjectDescription>,
+    },
+    // Objects grouped under their own BVH, which is faster to transform as a whole
+    Bvh {
+        objects: Vec<ObjectDescription>,
+    },
+    // A Wavefront OBJ file relative to the scene file, with its materials from its .mtl files.
+    // Exported scenes have the meshes written out instead
+    Obj {
+        path: PathBuf,
+    },
+    // A PLY file relative to the scene file, loaded as a triangle mesh. Its vertex colors are
+    // used by vertex_color textures
+    Ply {
+        path: PathBuf,
+        material: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Export(toml::ser::Error),
+    Undefined {
+        line: usize,
+        field: String,
+        kind: &'static str,
+        name: String,
+    },
+    TextureCycle {
+        line: usize,
+        name: String,
+    },
+    // A value the renderer can't do anything with
+    Invalid {
+        line: usize,
+        field: String,
+        problem: &'static str,
+    },
+    // Neither objects nor lights, so there'd be nothing to render
+    NoObjects,
+    Image {
+        line: usize,
+        path: PathBuf,
+        err: ImageError,
+    },
+    Mesh {
+        line: usize,
+        field: String,
+        err: MeshError,
+    },
+    Obj {
+        line: usize,
+        err: Box<ObjError>,
+    },
+    Ply {
+        line: usize,
+        err: Box<PlyError>,
+    },
+}
+
+impl Display for SceneFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneFileError::Io(err) => write!(f, "couldn't read the scene file: {err}"),
+            SceneFileError::Parse(err) => write!(f, "invalid scene file: {err}"),
+            SceneFileError::Export(err) => write!(f, "couldn't describe the scene: {err}"),
+            SceneFileError::Undefined {
+                line,
+                field,
+                kind,
+                name,
+            } => write!(
+                f,
+                "line {line}, field `{field}`: there's no {kind} named '{name}'"
+            ),
+            SceneFileError::TextureCycle { line, name } => {
+                write!(f, "line {line}: texture '{name}' contains itself")
+            }
+            SceneFileError::Invalid {
+                line,
+                field,
+                problem,
+            } => write!(f, "line {line}, field `{field}`: {problem}"),
+            SceneFileError::NoObjects => {
+                write!(f, "field `objects`: the scene has no objects or lights")
+            }
+            SceneFileError::Image { line, path, err } => write!(
+                f,
+                "line {line}, field `path`: couldn't load {}: {err}",
+                path.display()
+            ),
+            SceneFileError::Mesh { line, field, err } => {
+                write!(f, "line {line}, field `{field}`: {err}")
+            }
+            SceneFileError::Obj { line, err } => write!(f, "line {line}, field `path`: {err}"),
+            SceneFileError::Ply { line, err } => write!(f, "line {line}, field `path`: {err}"),
+        }
+    }
+}
+
+impl Error for SceneFileError {}
+
+impl From<io::Error> for SceneFileError {
+    fn from(err: io::Error) -> Self {
+        SceneFileError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for SceneFileError {
+    fn from(err: toml::de::Error) -> Self {
+        SceneFileError::Parse(err)
+    }
+}
+
+impl From<toml::ser::Error> for SceneFileError {
+    fn from(err: toml::ser::Error) -> Self {
+        SceneFileError::Export(err)
+    }
+}
+
+// Noise textures & BVHs are generated from the seed, so the same seed always gives the same scene
+pub fn load(path: &Path, seed: u64) -> Result<Scene, SceneFileError> {
+    let source = fs::read_to_string(path)?;
+
+    parse(&source, path.parent().unwrap_or(Path::new(".")), seed)
+}
+
+// Image textures are found relative to the directory
+pub fn parse(source: &str, directory: &Path, seed: u64) -> Result<Scene, SceneFileError> {
+    let file: SceneFile = toml::from_str(source)?;
+    let mut loader = Loader {
+        source,
+        directory,
+        texture_descriptions: &file.textures,
+        material_descriptions: &file.materials,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        building: HashSet::new(),
+        rng: RenderRng::seed_from_u64(seed),
+    };
+
+    // Every texture & material is built, so mistakes show up even if nothing uses them
+    for name in file.textures.keys() {
+        loader.texture(name, 0, "")?;
+    }
+    for name in file.materials.keys() {
+        loader.material(name, 0, "")?;
+    }
+
+    let mut objects = HittableList::new();
+    for object in &file.objects {
+        let line = loader.line(object.span());
+        objects.add(Arc::from(loader.object(object.get_ref(), line, "")?));
+    }
+    let mut lights = HittableList::new();
+    for light in &file.lights {
+        let line = loader.line(light.span());
+        let light: Arc<dyn Hittable> = Arc::from(loader.object(light.get_ref(), line, "")?);
+        lights.add(light.clone());
+        objects.add(light);
+    }
+
+    for (size, name) in [(&file.image.width, "width"), (&file.image.height, "height")] {
+        if *size.get_ref() < 1 {
+            return Err(SceneFileError::Invalid {
+                line: loader.line(size.span()),
+                field: format!("image.{name}"),
+                problem: "must be at least 1",
+            });
+        }
+    }
+    if file.objects.is_empty() && file.lights.is_empty() {
+        return Err(SceneFileError::NoObjects);
+    }
+    let image = Image {
+        width: *file.image.width.get_ref(),
+        height: *file.image.height.get_ref(),
+        samples_per_pixel: file.image.samples_per_pixel,
+    };
+    let camera = &file.camera;
+    let camera = Camera::new(
+        camera.look_from,
+        camera.look_at,
+        camera.view_up,
+        camera.vfov,
+        image.width as f64 / image.height as f64,
+        camera.aperture,
+        camera.focus_distance,
+        camera.shutter_open,
+        camera.shutter_close,
+    );
+
+    Ok(Scene {
+        objects,
+        lights,
+        background: file.background,
+        camera,
+        image,
+    })
+}
+
+// Write the scene out as a scene file which loads back into the same scene, except that noise
+// textures are generated again from the seed it's loaded with
+pub fn export(scene: &Scene, path: &Path) -> Result<(), SceneFileError> {
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    fs::write(path, to_string(scene, directory)?)?;
+
+    Ok(())
+}
+
+// Image textures are written relative to the directory the file will be in
+pub fn to_string(scene: &Scene, directory: &Path) -> Result<String, SceneFileError> {
+    let mut exporter = SceneExporter::new(directory);
+
+    // Lights are also in the objects, but are only written once
+    let mut objects = vec![];
+    let mut lights = vec![];
+    for object in scene.objects.objects() {
+        let description = Spanned::new(0..0, object.describe(&mut exporter));
+        match scene
+            .lights
+            .objects()
+            .iter()
+            .any(|light| Arc::ptr_eq(light, object))
+        {
+            true => lights.push(description),
+            false => objects.push(description),
+        }
+    }
+
+    let file = SceneFile {
+        background: scene.background,
+        image: ImageDescription {
+            width: Spanned::new(0..0, scene.image.width),
+            height: Spanned::new(0..0, scene.image.height),
+            samples_per_pixel: scene.image.samples_per_pixel,
+        },
+        camera: scene.camera.describe(),
+        textures: exporter
+            .textures
+            .into_iter()
+            .map(|(name, texture)| (name, Spanned::new(0..0, texture)))
+            .collect(),
+        materials: exporter
+            .materials
+            .into_iter()
+            .map(|(name, material)| (name, Spanned::new(0..0, material)))
+            .collect(),
+        objects,
+        lights,
+    };
+
+    Ok(toml::to_string(&file)?)
+}
+
+// Names the textures & materials of the objects being described. Ones described the same are
+// written once & shared
+pub struct SceneExporter<'a> {
+    directory: &'a Path,
+    textures: Vec<(String, TextureDescription)>,
+    materials: Vec<(String, MaterialDescription)>,
+}
+
+impl<'a> SceneExporter<'a> {
+    pub fn new(directory: &'a Path) -> Self {
+        Self {
+            directory,
+            textures: vec![],
+            materials: vec![],
+        }
+    }
+
+    pub fn material(&mut self, material: &dyn Material) -> String {
+        let description = material.describe(self);
+        let kind = match description {
+            MaterialDescription::Lambertian { .. } => "lambertian",
+            MaterialDescription::Metal { .. } => "metal",
+            MaterialDescription::Dielectric { .. } => "dielectric",
+            MaterialDescription::DiffuseLight { .. } => "diffuse_light",
+            MaterialDescription::Isotropic { .. } => "isotropic",
+        };
+
+        name(&mut self.materials, description, kind)
+    }
+
+    // Solid colors are written in place
+    pub fn texture(&mut self, texture: &dyn Texture) -> TextureRef {
+        let description = texture.describe(self);
+        let kind = match description {
+            TextureDescription::Solid { color } => return TextureRef::Color(color),
+            TextureDescription::Checker { .. } => "checker",
+            TextureDescription::Noise { .. } => "noise",
+            TextureDescription::Image { .. } => "image",
+            TextureDescription::VertexColor => "vertex_color",
+        };
+
+        TextureRef::Named(name(&mut self.textures, description, kind))
+    }
+
+    // The path from the scene file's directory, so the two can be moved together
+    pub fn image_path(&self, path: &Path) -> PathBuf {
+        let (Ok(path), Ok(directory)) = (
+            std::path::absolute(path),
+            std::path::absolute(self.directory),
+        ) else {
+            return path.to_path_buf();
+        };
+
+        let shared = path
+            .components()
+            .zip(directory.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        // Nothing in common but the root
+        if path
+            .components()
+            .take(shared)
+            .all(|component| matches!(component, Component::Prefix(_) | Component::RootDir))
+        {
+            return path;
+        }
+        let mut relative = PathBuf::new();
+        for _ in directory.components().skip(shared) {
+            relative.push("..");
+        }
+        relative.extend(path.components().skip(shared));
+
+        relative
+    }
+}
+
+// The name of the description, giving it the next one of its kind if it's new
+fn name<T: PartialEq>(named: &mut Vec<(String, T)>, description: T, kind: &str) -> String {
+    if let Some((name, _)) = named.iter().find(|(_, named)| *named == description) {
+        return name.clone();
+    }
+
+    let count = named
+        .iter()
+        .filter(|(name, _)| name.rsplit_once('_').map(|(prefix, _)| prefix) == Some(kind))
+        .count();
+    let name = format!("{kind}_{}", count + 1);
+    named.push((name.clone(), description));
+
+    name
+}
+
+struct Loader<'a> {
+    source: &'a str,
+    directory: &'a Path,
+    texture_descriptions: &'a BTreeMap<String, Spanned<TextureDescription>>,
+    material_descriptions: &'a BTreeMap<String, Spanned<MaterialDescription>>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    // Textures part way through being built, to catch ones which contain themselves
+    building: HashSet<String>,
+    rng: RenderRng,
+}
+
+impl<'a> Loader<'a> {
+    fn line(&self, span: Range<usize>) -> usize {
+        self.source[..span.start].matches('\n').count() + 1
+    }
+
+    // Built the first time it's referred to. Line & field are where the reference is, for errors
+    fn texture(
+        &mut self,
+        name: &str,
+        line: usize,
+        field: &str,
+    ) -> Result<Arc<dyn Texture>, SceneFileError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+
+        let description =
+            self.texture_descriptions
+                .get(name)
+                .ok_or_else(|| SceneFileError::Undefined {
+                    line,
+                    field: field.to_string(),
+                    kind: "texture",
+                    name: name.to_string(),
+                })?;
+        let line = self.line(description.span());
+        if !self.building.insert(name.to_string()) {
+            return Err(SceneFileError::TextureCycle {
+                line,
+                name: name.to_string(),
+            });
+        }
+
+        let texture: Arc<dyn Texture> = match description.get_ref() {
+            TextureDescription::Solid { color } => Arc::new(SolidColorTexture::new(*color)),
+            TextureDescription::Checker { odd, even } => Arc::new(CheckerTexture::new(
+                Box::new(self.texture_ref(odd, line, "odd")?),
+                Box::new(self.texture_ref(even, line, "even")?),
+            )),
+            TextureDescription::Noise { scale } => {
+                Arc::new(NoiseTexture::new(*scale, &mut self.rng))
+            }
+            TextureDescription::VertexColor => Arc::new(VertexColorTexture),
+            TextureDescription::Image { path } => {
+                let path = self.directory.join(path);
+                match ImageTexture::new(&path) {
+                    Ok(texture) => Arc::new(texture),
+                    Err(err) => return Err(SceneFileError::Image { line, path, err }),
+                }
+            }
+        };
+
+        self.building.remove(name);
+        self.textures.insert(name.to_string(), texture.clone());
+
+        Ok(texture)
+    }
+
+    fn texture_ref(
+        &mut self,
+        texture: &TextureRef,
+        line: usize,
+        field: &str,
+    ) -> Result<Arc<dyn Texture>, SceneFileError> {
+        match texture {
+            TextureRef::Color(color) => Ok(Arc::new(SolidColorTexture::new(*color))),
+            TextureRef::Named(name) => self.texture(name, line, field),
+        }
+    }
+
+    fn material(
+        &mut self,
+        name: &str,
+        line: usize,
+        field: &str,
+    ) -> Result<Arc<dyn Material>, SceneFileError> {
+        if let Some(material) = self.materials.get(name) {
+            return Ok(material.clone());
+        }
+
+        let description =
+            self.material_descriptions
+                .get(name)
+                .ok_or_else(|| SceneFileError::Undefined {
+                    line,
+                    field: field.to_string(),
+                    kind: "material",
+                    name: name.to_string(),
+                })?;
+        let line = self.line(description.span());
+
+        let material: Arc<dyn Material> = match description.get_ref() {
+            MaterialDescription::Lambertian { albedo } => {
+                Arc::new(Lambertian::new(self.texture_ref(albedo, line, "albedo")?))
+            }
+            MaterialDescription::Metal { albedo, fuzz } => Arc::new(Metal::new(*albedo, *fuzz)),
+            MaterialDescription::Dielectric { refraction_index } => {
+                Arc::new(Dielectric::new(*refraction_index))
+            }
+            MaterialDescription::DiffuseLight { emit } => Arc::new(DiffuseLight::new(Box::new(
+                self.texture_ref(emit, line, "emit")?,
+            ))),
+            MaterialDescription::Isotropic { albedo } => Arc::new(Isotropic::new(Box::new(
+                self.texture_ref(albedo, line, "albedo")?,
+            ))),
+        };
+
+        self.materials.insert(name.to_string(), material.clone());
+
+        Ok(material)
+    }
+
+    // Nested objects don't have their own spans, so errors give the line of the top level
+    // object & the path to the field from it
+    fn object(
+        &mut self,
+        object: &ObjectDescription,
+        line: usize,
+        path: &str,
+    ) -> Result<Box<dyn Hittable>, SceneFileError> {
+        let field = |name: &str| format!("{path}{name}");
+
+        Ok(match object {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere::new(
+                *center,
+                *radius,
+                self.material(material, line, &field("material"))?,
+            )),
+            ObjectDescription::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => Box::new(MovingSphere::new(
+                *center0,
+                *center1,
+                *time0,
+                *time1,
+                *radius,
+                self.material(material, line, &field("material"))?,
+            )),
+            ObjectDescription::XyRectangle {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Box::new(XyRectangle::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material(material, line, &field("material"))?,
+            )),
+            ObjectDescription::XzRectangle {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(XzRectangle::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material, line, &field("material"))?,
+            )),
+            ObjectDescription::YzRectangle {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(YzRectangle::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material, line, &field("material"))?,
+            )),
+            ObjectDescription::Cuboid { min, max, material } => Box::new(Cuboid::new(
+                *min,
+                *max,
+                self.material(material, line, &field("material"))?,
+            )),
+            ObjectDescription::Triangle {
+                vertices,
+                normals,
+                uvs,
+                colors,
+                material,
+            } => {
+                let buffers = MeshBuffers::new(
+                    vertices.to_vec(),
+                    normals.map(|normals| normals.to_vec()).unwrap_or_default(),
+                    uvs.map(|uvs| uvs.to_vec()).unwrap_or_default(),
+                    colors.map(|colors| colors.to_vec()).unwrap_or_default(),
+                    vec![[0, 1, 2]],
+                );
+                let buffers = buffers.map_err(|err| SceneFileError::Mesh {
+                    line,
+                    field: field("vertices"),
+                    err,
+                })?;
+
+                Box::new(Triangle::new(
+                    Arc::new(buffers),
+                    0,
+                    self.material(material, line, &field("material"))?,
+                ))
+            }
+            ObjectDescription::TriangleMesh {
+                positions,
+                normals,
+                uvs,
+                colors,
+                indices,
+                material,
+            } => {
+                let buffers = MeshBuffers::new(
+                    positions.clone(),
+                    normals.clone(),
+                    uvs.clone(),
+                    colors.clone(),
+                    indices.clone(),
+                );
+                let buffers = buffers.map_err(|err| SceneFileError::Mesh {
+                    line,
+                    field: field(match err {
+                        MeshError::NormalCount { .. } => "normals",
+                        MeshError::UvCount { .. } => "uvs",
+                        MeshError::ColorCount { .. } => "colors",
+                        MeshError::IndexOutOfRange { .. } | MeshError::NoTriangles => "indices",
+                    }),
+                    err,
+                })?;
+
+                Box::new(TriangleMesh::new(
+                    Arc::new(buffers),
+                    self.material(material, line, &field("material"))?,
+                ))
+            }
+            ObjectDescription::Translate { offset, object } => Box::new(Translate::new(
+                self.object(object, line, &field("object."))?,
+                *offset,
+            )),
+            ObjectDescription::Rotate { angle, object } => Box::new(Rotate::new(
+                self.object(object, line, &field("object."))?,
+                *angle,
+            )),
+            ObjectDescription::ConstantVolume {
+                density,
+                albedo,
+                boundary,
+            } => Box::new(ConstantVolume::new(
+                Arc::from(self.object(boundary, line, &field("boundary."))?),
+                *density,
+                Box::new(self.texture_ref(albedo, line, &field("albedo"))?),
+            )),
+            ObjectDescription::Bvh { objects } => {
+                let mut list = HittableList::new();
+                for (i, object) in objects.iter().enumerate() {
+                    let path = field(&format!("objects[{i}]."));
+                    list.add(Arc::from(self.object(object, line, &path)?));
+                }
+
+                let bvh = BVHNode::new(list, 0.0, 1.0, &mut self.rng).ok_or_else(|| {
+                    SceneFileError::Invalid {
+                        line,
+                        field: field("objects"),
+                        problem: "a BVH needs at least one object",
+                    }
+                })?;
+                Box::new(bvh)
+            }
+            ObjectDescription::Obj { path } => {
+                let path = self.directory.join(path);
+                let obj_error = |err| SceneFileError::Obj {
+                    line,
+                    err: Box::new(err),
+                };
+                let list = obj::load(&path).map_err(obj_error)?;
+                let bvh = BVHNode::new(list, 0.0, 1.0, &mut self.rng)
+                    .ok_or_else(|| obj_error(ObjError::NoFaces(path)))?;
+                Box::new(bvh)
+            }
+            ObjectDescription::Ply { path, material } => {
+                let material = self.material(material, line, &field("material"))?;
+                let mesh = ply::load(&self.directory.join(path), material).map_err(|err| {
+                    SceneFileError::Ply {
+                        line,
+                        err: Box::new(err),
+                    }
+                })?;
+
+                Box::new(mesh)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        render::{test_settings, RenderSettings, Renderer},
+        scene::{build_scene, Scene, SCENES},
+    };
+
+    use super::{parse, to_string};
+
+    fn render(mut scene: Scene) -> Vec<f64> {
+        scene.image.resize(Some(16), Some(16));
+        let renderer = Renderer::new(
+            scene,
+            RenderSettings {
+                tile_size: 16,
+                ..test_settings()
+            },
+        );
+        let mut film = renderer.film(false);
+        renderer.render(&mut film, &[4; 16 * 16]);
+
+        film.resolve()
+            .pixels()
+            .iter()
+            .flat_map(|pixel| [pixel.x, pixel.y, pixel.z])
+            .collect()
+    }
+
+    #[test]
+    pub fn cornell_box_file_matches_the_built_in_scene() {
+        let file = build_scene("scenes/cornell_box.toml", &[], 0).unwrap();
+        let built_in = build_scene("cornell_box", &[], 0).unwrap();
+
+        assert_eq!(built_in.image.width, file.image.width);
+        assert_eq!(built_in.image.height, file.image.height);
+        assert_eq!(render(built_in), render(file));
+    }
+
+    #[test]
+    pub fn built_in_scenes_load_back_from_their_export() {
+        for entry in SCENES {
+            // Keep the random scenes small
+            let params = vec![2; entry.params.len()];
+            let scene = build_scene(entry.name, &params, 0).unwrap();
+            let exported = to_string(&scene, Path::new(".")).unwrap();
+            let loaded = parse(&exported, Path::new("."), 0).unwrap();
+
+            assert_eq!(
+                scene.objects.objects().len(),
+                loaded.objects.objects().len(),
+                "{}",
+                entry.name
+            );
+            assert_eq!(
+                scene.lights.objects().len(),
+                loaded.lights.objects().len(),
+                "{}",
+                entry.name
+            );
+        }
+
+        // Only the order of the objects changes, which doesn't change what's hit
+        let exported = to_string(&build_scene("cornell_box", &[], 0).unwrap(), Path::new("."));
+        assert_eq!(
+            render(build_scene("cornell_box", &[], 0).unwrap()),
+            render(parse(&exported.unwrap(), Path::new("."), 0).unwrap())
+        );
+    }
+
+    #[test]
+    pub fn errors_give_the_line_and_field() {
+        let error = |source: &str| match parse(source, Path::new("."), 0) {
+            Ok(_) => panic!("{source} should fail to load"),
+            Err(err) => err.to_string(),
+        };
+        let header = r#"
+[image]
+width = 10
+height = 10
+samples_per_pixel = 1
+
+[camera]
+look_from = [0.0, 0.0, 1.0]
+look_at = [0.0, 0.0, 0.0]
+vfov = 40.0
+"#;
+
+        let missing = error(&format!(
+            "{header}\n[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nmaterial = \"red\"\n"
+        ));
+        assert!(missing.contains("line 12"), "{missing}");
+        assert!(missing.contains("radius"), "{missing}");
+
+        let undefined = error(&format!(
+            "{header}\n[[objects]]\ntype = \"translate\"\noffset = [0.0, 0.0, 0.0]\n\
+             object = {{ type = \"sphere\", center = [0.0, 0.0, 0.0], radius = 1.0, material = \"red\" }}\n"
+        ));
+        assert!(undefined.contains("line 12"), "{undefined}");
+        assert!(undefined.contains("`object.material`"), "{undefined}");
+        assert!(undefined.contains("'red'"), "{undefined}");
+
+        let cycle = error(&format!(
+            "{header}\n[textures.a]\ntype = \"checker\"\nodd = \"a\"\neven = [1.0, 1.0, 1.0]\n"
+        ));
+        assert!(cycle.contains("line 12"), "{cycle}");
+
+        let empty = error(&format!(
+            "{header}\n[[objects]]\ntype = \"translate\"\noffset = [0.0, 0.0, 0.0]\n\
+             object = {{ type = \"bvh\", objects = [] }}\n"
+        ));
+        assert!(empty.contains("line 12"), "{empty}");
+        assert!(empty.contains("`object.objects`"), "{empty}");
+
+        let size = error(&header.replace("height = 10", "height = 0"));
+        assert!(size.contains("line 4"), "{size}");
+        assert!(size.contains("`image.height`"), "{size}");
+
+        let nothing = error(header);
+        assert!(nothing.contains("`objects`"), "{nothing}");
+
+        let no_triangles = error(&format!(
+            "{header}\n[[objects]]\ntype = \"triangle_mesh\"\npositions = []\nindices = []\n\
+             material = \"red\"\n"
+        ));
+        assert!(no_triangles.contains("line 12"), "{no_triangles}");
+        assert!(no_triangles.contains("`indices`"), "{no_triangles}");
+    }
+}
@@ -4,9 +4,11 @@ use crate::{
     aabb::AABB,
     hittable::{HitRecord, Hittable},
     material::Material,
-    math::Vector,
+    math::{sample_cone, to_basis, RenderRng, Vector},
     ray::Ray,
-    Point,
+    sampler::Sampler,
+    scene_file::{ObjectDescription, SceneExporter},
+    stats, Point, MIN_INTERSECTION_DISTANCE,
 };
 
 pub struct Sphere {
@@ -26,7 +28,8 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _: &mut RenderRng) -> Option<HitRecord> {
+        stats::count_primitive_test();
         let oc = ray.origin() - self.center;
         let a = ray.direction().magnitude_squared();
         let half_b = oc.dot(&ray.direction());
@@ -67,6 +70,46 @@ impl Hittable for Sphere {
             self.center + Vector::new(self.radius, self.radius, self.radius),
         ))
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.material.clone());
+    }
+
+    // Directions are sampled uniformly over the cone the sphere covers as seen from origin
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, rng: &mut RenderRng) -> f64 {
+        let ray = Ray::new(origin, direction, time);
+        if self
+            .hit(&ray, MIN_INTERSECTION_DISTANCE, f64::INFINITY, rng)
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+            None => 0.0,
+        }
+    }
+
+    fn sample_direction(&self, origin: Point, _: f64, sampler: &mut dyn Sampler) -> Vector {
+        let sample = sampler.get_2d();
+
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => {
+                to_basis(&sample_cone(sample, cos_theta_max), &(self.center - origin))
+            }
+            // There's no cone from inside the sphere, which pdf_value treats as never sampled
+            None => Vector::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::Sphere {
+            center: self.center,
+            radius: self.radius,
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
 }
 
 pub struct MovingSphere {
@@ -105,7 +148,8 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _: &mut RenderRng) -> Option<HitRecord> {
+        stats::count_primitive_test();
         let oc = ray.origin() - self.center(ray.time());
         let a = ray.direction().magnitude_squared();
         let half_b = oc.dot(&ray.direction());
@@ -152,6 +196,33 @@ impl Hittable for MovingSphere {
 
         Some(AABB::surrounding_box(&start_box, &end_box))
     }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.material.clone());
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::MovingSphere {
+            center0: self.start_center,
+            center1: self.end_center,
+            time0: self.start_time,
+            time1: self.end_time,
+            radius: self.radius,
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
+}
+
+impl Sphere {
+    // The cosine of the half angle of the cone the sphere covers as seen from outside it
+    fn cos_theta_max(&self, origin: Point) -> Option<f64> {
+        let distance_squared = (self.center - origin).magnitude_squared();
+        if distance_squared <= self.radius.powi(2) {
+            return None;
+        }
+
+        Some((1.0 - self.radius.powi(2) / distance_squared).sqrt())
+    }
 }
 
 fn get_sphere_uv(p: Point) -> (f64, f64) {
@@ -166,10 +237,12 @@ fn get_sphere_uv(p: Point) -> (f64, f64) {
 mod tests {
     use std::sync::Arc;
 
+    use rand::SeedableRng;
+
     use crate::{
         hittable::{Face, Hittable},
         material::Lambertian,
-        math::{Color, Point, Vector},
+        math::{Color, Point, RenderRng, Vector},
         ray::Ray,
         MIN_INTERSECTION_DISTANCE,
     };
@@ -191,11 +264,14 @@ mod tests {
             0.0,
         );
 
-        let hit = sphere.hit(&ray, 0.0, f64::MAX).unwrap();
+        let mut rng = RenderRng::seed_from_u64(0);
+
+        let hit = sphere.hit(&ray, 0.0, f64::MAX, &mut rng).unwrap();
         let second_hit = sphere.hit(
             &Ray::new(hit.p, hit.normal, 0.0),
             MIN_INTERSECTION_DISTANCE,
             f64::MAX,
+            &mut rng,
         );
 
         assert_eq!(Face::Front, hit.face);
@@ -0,0 +1,208 @@
+use std::{
+    cell::Cell,
+    fmt::Display,
+    ops::{AddAssign, Sub},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+// Counters for the work this thread has done. They only ever go up, so the work done between
+// two points is the difference of the counts taken at each
+struct Counters {
+    camera_rays: Cell<u64>,
+    rays: Cell<u64>,
+    path_vertices: Cell<u64>,
+    primitive_tests: Cell<u64>,
+    aabb_tests: Cell<u64>,
+}
+
+thread_local! {
+    static COUNTERS: Counters = const {
+        Counters {
+            camera_rays: Cell::new(0),
+            rays: Cell::new(0),
+            path_vertices: Cell::new(0),
+            primitive_tests: Cell::new(0),
+            aabb_tests: Cell::new(0),
+        }
+    };
+}
+
+#[inline]
+fn increment(counter: impl FnOnce(&Counters) -> &Cell<u64>) {
+    COUNTERS.with(|counters| {
+        let counter = counter(counters);
+        counter.set(counter.get() + 1);
+    });
+}
+
+#[inline]
+pub fn count_camera_ray() {
+    increment(|counters| &counters.camera_rays);
+}
+
+// Any ray traced through the scene, camera rays included
+#[inline]
+pub fn count_ray() {
+    increment(|counters| &counters.rays);
+}
+
+// A surface hit along a camera ray's path
+#[inline]
+pub fn count_path_vertex() {
+    increment(|counters| &counters.path_vertices);
+}
+
+#[inline]
+pub fn count_primitive_test() {
+    increment(|counters| &counters.primitive_tests);
+}
+
+#[inline]
+pub fn count_aabb_test() {
+    increment(|counters| &counters.aabb_tests);
+}
+
+// Runs f without counting its primitive & AABB tests. Light pdfs are found by intersecting the
+// lights, but those aren't rays traced through the scene so would skew the tests per ray
+pub fn uncounted<T>(f: impl FnOnce() -> T) -> T {
+    let before = counts();
+    let result = f();
+    COUNTERS.with(|counters| {
+        counters.primitive_tests.set(before.primitive_tests);
+        counters.aabb_tests.set(before.aabb_tests);
+    });
+
+    result
+}
+
+// This thread's counts so far
+pub fn counts() -> RayCounts {
+    COUNTERS.with(|counters| RayCounts {
+        camera_rays: counters.camera_rays.get(),
+        rays: counters.rays.get(),
+        path_vertices: counters.path_vertices.get(),
+        primitive_tests: counters.primitive_tests.get(),
+        aabb_tests: counters.aabb_tests.get(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RayCounts {
+    pub camera_rays: u64,
+    pub rays: u64,
+    pub path_vertices: u64,
+    pub primitive_tests: u64,
+    pub aabb_tests: u64,
+}
+
+impl Sub for RayCounts {
+    type Output = RayCounts;
+
+    fn sub(self, other: RayCounts) -> RayCounts {
+        RayCounts {
+            camera_rays: self.camera_rays - other.camera_rays,
+            rays: self.rays - other.rays,
+            path_vertices: self.path_vertices - other.path_vertices,
+            primitive_tests: self.primitive_tests - other.primitive_tests,
+            aabb_tests: self.aabb_tests - other.aabb_tests,
+        }
+    }
+}
+
+impl AddAssign for RayCounts {
+    fn add_assign(&mut self, other: RayCounts) {
+        self.camera_rays += other.camera_rays;
+        self.rays += other.rays;
+        self.path_vertices += other.path_vertices;
+        self.primitive_tests += other.primitive_tests;
+        self.aabb_tests += other.aabb_tests;
+    }
+}
+
+// Summary of a finished render, for spotting performance regressions between builds
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderStats {
+    pub camera_rays: u64,
+    // Scattered & shadow rays
+    pub secondary_rays: u64,
+    // Surfaces hit per camera ray
+    pub mean_path_length: f64,
+    pub primitive_tests_per_ray: f64,
+    pub aabb_tests_per_ray: f64,
+    // Just the BVH over the scene's objects, meshes build theirs as the scene is loaded
+    pub top_level_bvh_build_seconds: f64,
+    pub render_seconds: f64,
+    pub mrays_per_second: f64,
+}
+
+impl RenderStats {
+    pub fn new(counts: RayCounts, bvh_build_time: Duration, render_time: Duration) -> Self {
+        let per = |count: u64, total: u64| match total {
+            0 => 0.0,
+            total => count as f64 / total as f64,
+        };
+        let render_seconds = render_time.as_secs_f64();
+
+        Self {
+            camera_rays: counts.camera_rays,
+            secondary_rays: counts.rays.saturating_sub(counts.camera_rays),
+            mean_path_length: per(counts.path_vertices, counts.camera_rays),
+            primitive_tests_per_ray: per(counts.primitive_tests, counts.rays),
+            aabb_tests_per_ray: per(counts.aabb_tests, counts.rays),
+            top_level_bvh_build_seconds: bvh_build_time.as_secs_f64(),
+            render_seconds,
+            mrays_per_second: match render_seconds > 0.0 {
+                true => counts.rays as f64 / render_seconds / 1e6,
+                false => 0.0,
+            },
+        }
+    }
+}
+
+impl Display for RenderStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Camera rays:              {}", self.camera_rays)?;
+        writeln!(f, "Secondary rays:           {}", self.secondary_rays)?;
+        writeln!(f, "Mean path length:         {:.2}", self.mean_path_length)?;
+        writeln!(
+            f,
+            "Primitive tests per ray:  {:.2}",
+            self.primitive_tests_per_ray
+        )?;
+        writeln!(
+            f,
+            "AABB tests per ray:       {:.2}",
+            self.aabb_tests_per_ray
+        )?;
+        writeln!(
+            f,
+            "Top-level BVH build time: {:.3}s",
+            self.top_level_bvh_build_seconds
+        )?;
+        writeln!(f, "Render time:              {:.3}s", self.render_seconds)?;
+        write!(f, "Mrays/s:                  {:.3}", self.mrays_per_second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_aabb_test, count_primitive_test, count_ray, counts, uncounted};
+
+    #[test]
+    pub fn uncounted_tests_are_left_out() {
+        let before = counts();
+        count_primitive_test();
+        uncounted(|| {
+            count_primitive_test();
+            count_aabb_test();
+            count_ray();
+        });
+        let counted = counts() - before;
+
+        assert_eq!(1, counted.primitive_tests);
+        assert_eq!(0, counted.aabb_tests);
+        assert_eq!(1, counted.rays);
+    }
+}
@@ -1,14 +1,34 @@
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use image::{DynamicImage, GenericImageView, ImageError};
+use rand::Rng;
 
 use crate::{
-    math::{Color, Point},
+    hittable::HitRecord,
+    math::Color,
     perlin::{Perlin, DEFAULT_TURBULENCE_DEPTH},
+    scene_file::{SceneExporter, TextureDescription},
 };
 
+// Textures are looked up at a hit, mostly by its u, v & p
 pub trait Texture: Send + Sync {
-    fn value(&self, u: f64, v: f64, p: Point) -> Color;
+    fn value(&self, hit: &HitRecord) -> Color;
+    // How the texture is written in a scene file
+    fn describe(&self, exporter: &mut SceneExporter) -> TextureDescription;
+}
+
+// Lets a shared texture be used where one is owned
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, hit: &HitRecord) -> Color {
+        (**self).value(hit)
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> TextureDescription {
+        (**self).describe(exporter)
+    }
 }
 
 pub struct SolidColorTexture {
@@ -22,9 +42,13 @@ impl SolidColorTexture {
 }
 
 impl Texture for SolidColorTexture {
-    fn value(&self, _: f64, _: f64, _: Point) -> Color {
+    fn value(&self, _: &HitRecord) -> Color {
         self.color
     }
+
+    fn describe(&self, _: &mut SceneExporter) -> TextureDescription {
+        TextureDescription::Solid { color: self.color }
+    }
 }
 
 pub struct CheckerTexture {
@@ -46,13 +70,21 @@ impl CheckerTexture {
 }
 
 impl Texture for CheckerTexture {
-    fn value(&self, u: f64, v: f64, p: Point) -> Color {
+    fn value(&self, hit: &HitRecord) -> Color {
+        let p = hit.p;
         let sines = (10.0 * p.x).sin() * (10.0 * p.y).sin() * (10.0 * p.z).sin();
 
         if sines < 0.0 {
-            self.odd.value(u, v, p)
+            self.odd.value(hit)
         } else {
-            self.even.value(u, v, p)
+            self.even.value(hit)
+        }
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> TextureDescription {
+        TextureDescription::Checker {
+            odd: exporter.texture(self.odd.as_ref()),
+            even: exporter.texture(self.even.as_ref()),
         }
     }
 }
@@ -63,16 +95,17 @@ pub struct NoiseTexture {
 }
 
 impl NoiseTexture {
-    pub fn new(scale: f64) -> Self {
+    pub fn new<R: Rng + ?Sized>(scale: f64, rng: &mut R) -> Self {
         Self {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale,
         }
     }
 }
 
 impl Texture for NoiseTexture {
-    fn value(&self, _: f64, _: f64, p: Point) -> Color {
+    fn value(&self, hit: &HitRecord) -> Color {
+        let p = hit.p;
         // Use the turbulent noise to perturb a sine wave, & use that to modify the color.
         // Gives a marlbed wave effect.
         // Lots of scope for material generation here if we expose some of the noise settings to the caller
@@ -82,25 +115,34 @@ impl Texture for NoiseTexture {
                 + (self.scale * p.z + 10.0 * self.noise.turbulence(p, DEFAULT_TURBULENCE_DEPTH))
                     .sin())
     }
+
+    // The noise isn't written, so it's generated again when the file is loaded
+    fn describe(&self, _: &mut SceneExporter) -> TextureDescription {
+        TextureDescription::Noise { scale: self.scale }
+    }
 }
 
 pub struct ImageTexture {
     image: DynamicImage,
+    path: PathBuf,
 }
 
 impl ImageTexture {
     pub fn new(path: &Path) -> Result<Self, ImageError> {
         let image = image::open(path)?;
 
-        Ok(Self { image })
+        Ok(Self {
+            image,
+            path: path.to_path_buf(),
+        })
     }
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, _: Point) -> Color {
+    fn value(&self, hit: &HitRecord) -> Color {
         // Clamp input coordinates to [0, 1] x [1, 0]
-        let u = u.clamp(0.0, 1.0);
-        let v = 1.0 - v.clamp(0.0, 1.0); // Flip v to match image coordinates
+        let u = hit.u.clamp(0.0, 1.0);
+        let v = 1.0 - hit.v.clamp(0.0, 1.0); // Flip v to match image coordinates
 
         let mut i = (u * self.image.width() as f64) as u32;
         let mut j = (v * self.image.height() as f64) as u32;
@@ -122,4 +164,24 @@ impl Texture for ImageTexture {
             color_scale * pixel.0[2] as f64,
         )
     }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> TextureDescription {
+        TextureDescription::Image {
+            path: exporter.image_path(&self.path),
+        }
+    }
+}
+
+// The color interpolated from the vertices of a mesh, for meshes like scans which are colored
+// per vertex. Surfaces without vertex colors are black
+pub struct VertexColorTexture;
+
+impl Texture for VertexColorTexture {
+    fn value(&self, hit: &HitRecord) -> Color {
+        hit.vertex_color.unwrap_or(Color::zeros())
+    }
+
+    fn describe(&self, _: &mut SceneExporter) -> TextureDescription {
+        TextureDescription::VertexColor
+    }
 }
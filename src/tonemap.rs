@@ -0,0 +1,214 @@
+use clap::ValueEnum;
+
+use crate::math::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ToneMapOperator {
+    /// Clip each channel at 1
+    Clamp,
+    /// Reinhard on luminance, L / (1 + L)
+    Reinhard,
+    /// Reinhard on luminance, mapping the white point to 1
+    ReinhardExtended,
+    /// John Hable's Uncharted 2 filmic curve
+    Hable,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TransferFunction {
+    /// The piecewise sRGB OETF
+    Srgb,
+    /// A plain square root, this is what the renderer used to output
+    Gamma2,
+    /// No encoding, write the tone mapped values as is
+    Linear,
+}
+
+// Turns linear radiance into display referred values in [0, 1]
+pub struct DisplayTransform {
+    // In stops, each stop doubles the brightness
+    pub exposure: f64,
+    pub operator: ToneMapOperator,
+    // The smallest luminance mapped to pure white by the extended Reinhard & Hable operators
+    pub white_point: f64,
+    pub transfer: TransferFunction,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            operator: ToneMapOperator::Clamp,
+            white_point: 4.0,
+            transfer: TransferFunction::Srgb,
+        }
+    }
+}
+
+impl DisplayTransform {
+    pub fn apply(&self, color: Color) -> Color {
+        // Treat anything negative as black before it can confuse the operators
+        let color = color.map(|c| c.max(0.0)) * 2.0_f64.powf(self.exposure);
+
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapOperator::ReinhardExtended => {
+                let white_squared = self.white_point.powi(2);
+                scale_luminance(color, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            }
+            ToneMapOperator::Hable => {
+                // The curve is designed for an exposure bias of 2
+                let white_scale = 1.0 / hable(2.0 * self.white_point);
+                color.map(|c| hable(2.0 * c) * white_scale)
+            }
+            ToneMapOperator::Aces => color.map(aces),
+        };
+
+        mapped.map(|c| match self.transfer {
+            TransferFunction::Srgb => srgb_oetf(c.clamp(0.0, 1.0)),
+            TransferFunction::Gamma2 => c.clamp(0.0, 1.0).sqrt(),
+            TransferFunction::Linear => c.clamp(0.0, 1.0),
+        })
+    }
+
+    pub fn to_rgb8(&self, color: Color) -> [u8; 3] {
+        let color = self.apply(color);
+
+        [
+            (256.0 * color.x.clamp(0.0, 0.999)) as u8,
+            (256.0 * color.y.clamp(0.0, 0.999)) as u8,
+            (256.0 * color.z.clamp(0.0, 0.999)) as u8,
+        ]
+    }
+}
+
+#[inline]
+pub fn luminance(color: Color) -> f64 {
+    // Rec. 709 weights, which share primaries with sRGB
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// Map t in [0, 1] through black, red, yellow & white, for visualising counts
+pub fn heat_color(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0) * 3.0;
+
+    Color::new(
+        t.min(1.0),
+        (t - 1.0).clamp(0.0, 1.0),
+        (t - 2.0).clamp(0.0, 1.0),
+    )
+}
+
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return color;
+    }
+
+    color * (curve(l) / l)
+}
+
+fn hable(x: f64) -> f64 {
+    let a = 0.15; // Shoulder strength
+    let b = 0.50; // Linear strength
+    let c = 0.10; // Linear angle
+    let d = 0.20; // Toe strength
+    let e = 0.02; // Toe numerator
+    let f = 0.30; // Toe denominator
+
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn aces(x: f64) -> f64 {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+fn srgb_oetf(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::Color;
+
+    use super::{DisplayTransform, ToneMapOperator, TransferFunction};
+
+    #[test]
+    pub fn srgb_end_points() {
+        let transform = DisplayTransform::default();
+
+        assert_eq!(
+            Color::new(0.0, 0.0, 0.0),
+            transform.apply(Color::new(0.0, 0.0, 0.0))
+        );
+        assert!((transform.apply(Color::new(1.0, 1.0, 1.0)).x - 1.0).abs() < 1e-12);
+        assert!((transform.apply(Color::new(0.18, 0.18, 0.18)).x - 0.4614).abs() < 1e-4);
+    }
+
+    #[test]
+    pub fn exposure_doubles_per_stop() {
+        let transform = DisplayTransform {
+            exposure: 2.0,
+            transfer: TransferFunction::Linear,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Color::new(0.4, 0.2, 0.1),
+            transform.apply(Color::new(0.1, 0.05, 0.025))
+        );
+    }
+
+    #[test]
+    pub fn operators_stay_in_range_and_monotonic() {
+        for operator in [
+            ToneMapOperator::Reinhard,
+            ToneMapOperator::ReinhardExtended,
+            ToneMapOperator::Hable,
+            ToneMapOperator::Aces,
+        ] {
+            let transform = DisplayTransform {
+                operator,
+                transfer: TransferFunction::Linear,
+                ..Default::default()
+            };
+
+            let mut previous = 0.0;
+            for i in 0..1000 {
+                let value = transform
+                    .apply(Color::new(1.0, 1.0, 1.0) * i as f64 * 0.05)
+                    .x;
+                assert!((0.0..=1.0).contains(&value), "{operator:?} gave {value}");
+                assert!(value >= previous, "{operator:?} isn't monotonic");
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    pub fn white_point_maps_to_white() {
+        for operator in [ToneMapOperator::ReinhardExtended, ToneMapOperator::Hable] {
+            let transform = DisplayTransform {
+                operator,
+                white_point: 8.0,
+                transfer: TransferFunction::Linear,
+                ..Default::default()
+            };
+
+            assert!((transform.apply(Color::new(8.0, 8.0, 8.0)).x - 1.0).abs() < 1e-9);
+        }
+    }
+}
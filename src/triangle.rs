@@ -0,0 +1,976 @@
+use std::{error::Error, fmt::Display, sync::Arc};
+
+use crate::{
+    aabb::AABB,
+    hittable::{Face, HitRecord, Hittable},
+    material::Material,
+    math::{Color, Point, RenderRng, Vector},
+    ray::Ray,
+    sampler::Sampler,
+    scene_file::{ObjectDescription, SceneExporter},
+    stats, MIN_INTERSECTION_DISTANCE,
+};
+
+// Leaves with this many triangles or fewer aren't split if splitting doesn't pay off
+const MAX_LEAF_TRIANGLES: usize = 4;
+// Buckets the centroids are sorted into when looking for the cheapest split
+const SAH_BINS: usize = 12;
+// Deeper nodes are made leaves, so traversal can use a fixed size stack
+const MAX_BVH_DEPTH: usize = 48;
+// Bounding boxes are padded to at least this thick, otherwise flat meshes couldn't be hit
+const MIN_BOX_THICKNESS: f64 = 0.0001;
+
+#[derive(Debug)]
+pub enum MeshError {
+    NormalCount { vertices: usize, normals: usize },
+    UvCount { vertices: usize, uvs: usize },
+    ColorCount { vertices: usize, colors: usize },
+    IndexOutOfRange { triangle: usize, index: u32 },
+    NoTriangles,
+}
+
+impl Display for MeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshError::NormalCount { vertices, normals } => {
+                write!(f, "the mesh has {vertices} vertices but {normals} normals")
+            }
+            MeshError::UvCount { vertices, uvs } => {
+                write!(f, "the mesh has {vertices} vertices but {uvs} uvs")
+            }
+            MeshError::ColorCount { vertices, colors } => {
+                write!(f, "the mesh has {vertices} vertices but {colors} colors")
+            }
+            MeshError::IndexOutOfRange { triangle, index } => write!(
+                f,
+                "triangle {triangle} uses vertex {index}, which doesn't exist"
+            ),
+            MeshError::NoTriangles => write!(f, "the mesh has no triangles"),
+        }
+    }
+}
+
+impl Error for MeshError {}
+
+// Vertex & index buffers shared by the triangles of a mesh
+pub struct MeshBuffers {
+    positions: Vec<Point>,
+    // One per vertex for smooth shading, or empty for flat shading
+    normals: Vec<Vector>,
+    // One per vertex, or empty to use the barycentric coordinates
+    uvs: Vec<[f64; 2]>,
+    // One per vertex for the vertex color texture, or empty
+    colors: Vec<Color>,
+    indices: Vec<[u32; 3]>,
+}
+
+impl MeshBuffers {
+    pub fn new(
+        positions: Vec<Point>,
+        normals: Vec<Vector>,
+        uvs: Vec<[f64; 2]>,
+        colors: Vec<Color>,
+        indices: Vec<[u32; 3]>,
+    ) -> Result<Self, MeshError> {
+        if indices.is_empty() {
+            return Err(MeshError::NoTriangles);
+        }
+        if !normals.is_empty() && normals.len() != positions.len() {
+            return Err(MeshError::NormalCount {
+                vertices: positions.len(),
+                normals: normals.len(),
+            });
+        }
+        if !uvs.is_empty() && uvs.len() != positions.len() {
+            return Err(MeshError::UvCount {
+                vertices: positions.len(),
+                uvs: uvs.len(),
+            });
+        }
+        if !colors.is_empty() && colors.len() != positions.len() {
+            return Err(MeshError::ColorCount {
+                vertices: positions.len(),
+                colors: colors.len(),
+            });
+        }
+        for (triangle, vertices) in indices.iter().enumerate() {
+            if let Some(&index) = vertices
+                .iter()
+                .find(|&&index| index as usize >= positions.len())
+            {
+                return Err(MeshError::IndexOutOfRange { triangle, index });
+            }
+        }
+
+        Ok(Self {
+            positions,
+            normals,
+            uvs,
+            colors,
+            indices,
+        })
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    #[inline]
+    fn vertices(&self, triangle: usize) -> [Point; 3] {
+        self.indices[triangle].map(|index| self.positions[index as usize])
+    }
+
+    // Not normalized, its length is twice the triangle's area
+    fn geometric_normal(&self, triangle: usize) -> Vector {
+        let [p0, p1, p2] = self.vertices(triangle);
+
+        (p1 - p0).cross(&(p2 - p0))
+    }
+
+    fn area(&self, triangle: usize) -> f64 {
+        self.geometric_normal(triangle).magnitude() / 2.0
+    }
+
+    fn bounds(&self, triangle: usize) -> (Point, Point) {
+        let [p0, p1, p2] = self.vertices(triangle);
+
+        (p0.inf(&p1).inf(&p2), p0.sup(&p1).sup(&p2))
+    }
+
+    // Watertight ray-triangle intersection (Woop, Benthin & Wald 2013). The ray is sheared so it
+    // points down z from the origin, then the edges are tested in 2D, so a ray through an edge
+    // shared by two triangles always hits one of them. Gives t & the barycentric coordinates
+    fn intersect(
+        &self,
+        triangle: usize,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, [f64; 3])> {
+        stats::count_primitive_test();
+        let direction = ray.direction();
+
+        // Make z the direction's largest dimension, keeping the winding the same
+        let kz = direction.iamax();
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        if direction[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+        let sx = direction[kx] / direction[kz];
+        let sy = direction[ky] / direction[kz];
+        let sz = 1.0 / direction[kz];
+
+        let [a, b, c] = self.vertices(triangle).map(|p| p - ray.origin());
+        let (ax, ay) = (a[kx] - sx * a[kz], a[ky] - sy * a[kz]);
+        let (bx, by) = (b[kx] - sx * b[kz], b[ky] - sy * b[kz]);
+        let (cx, cy) = (c[kx] - sx * c[kz], c[ky] - sy * c[kz]);
+
+        // Scaled barycentric coordinates, from the signed area of each edge with the ray
+        let u = cx * by - cy * bx;
+        let v = ax * cy - ay * cx;
+        let w = bx * ay - by * ax;
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+
+        let determinant = u + v + w;
+        if determinant == 0.0 {
+            return None;
+        }
+
+        let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / determinant;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        Some((t, [u / determinant, v / determinant, w / determinant]))
+    }
+
+    fn hit_record(
+        &self,
+        triangle: usize,
+        t: f64,
+        barycentrics: [f64; 3],
+        ray: &Ray,
+        material: Arc<dyn Material>,
+    ) -> HitRecord {
+        let indices = self.indices[triangle].map(|index| index as usize);
+        let interpolate = |values: [Vector; 3]| {
+            values
+                .iter()
+                .zip(barycentrics)
+                .map(|(value, weight)| value * weight)
+                .sum::<Vector>()
+        };
+
+        let [u, v] = match self.uvs.is_empty() {
+            true => [barycentrics[1], barycentrics[2]],
+            false => {
+                let uv = interpolate(indices.map(|i| {
+                    let [u, v] = self.uvs[i];
+                    Vector::new(u, v, 0.0)
+                }));
+                [uv.x, uv.y]
+            }
+        };
+
+        // The face is decided by the geometric normal, shading normals only bend the lighting
+        let geometric = self.geometric_normal(triangle).normalize();
+        let mut hit = HitRecord::new(t, u, v, geometric, ray, material);
+        if !self.colors.is_empty() {
+            hit.vertex_color = Some(interpolate(indices.map(|i| self.colors[i])));
+        }
+        if !self.normals.is_empty() {
+            // Zero normals, or ones which cancel out, leave the geometric normal
+            let shading = interpolate(indices.map(|i| self.normals[i]))
+                .try_normalize(f64::EPSILON)
+                .filter(|shading| shading.iter().all(|c| c.is_finite()));
+            if let Some(shading) = shading {
+                let outward = match shading.dot(&geometric) < 0.0 {
+                    true => -shading,
+                    false => shading,
+                };
+                hit.normal = match hit.face {
+                    Face::Front => outward,
+                    Face::Back => -outward,
+                };
+            }
+        }
+
+        hit
+    }
+
+    // A point spread evenly over the triangle
+    fn sample_point(&self, triangle: usize, (r1, r2): (f64, f64)) -> Point {
+        let [p0, p1, p2] = self.vertices(triangle);
+        let root = r1.sqrt();
+
+        p0 + root * (1.0 - r2) * (p1 - p0) + root * r2 * (p2 - p0)
+    }
+}
+
+// The density of sampling the direction to a point on a surface picked evenly by area
+fn area_pdf(distance_squared: f64, direction: Vector, normal: Vector, area: f64) -> f64 {
+    let cosine = direction.dot(&normal).abs() / (direction.magnitude() * normal.magnitude());
+
+    distance_squared / (cosine * area)
+}
+
+// A single triangle of a mesh's buffers
+pub struct Triangle {
+    buffers: Arc<MeshBuffers>,
+    index: usize,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(buffers: Arc<MeshBuffers>, index: usize, material: Arc<dyn Material>) -> Self {
+        Self {
+            buffers,
+            index,
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _: &mut RenderRng) -> Option<HitRecord> {
+        let (t, barycentrics) = self.buffers.intersect(self.index, ray, t_min, t_max)?;
+
+        Some(
+            self.buffers
+                .hit_record(self.index, t, barycentrics, ray, self.material.clone()),
+        )
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
+        let (min, max) = self.buffers.bounds(self.index);
+        let (min, max) = pad(min, max);
+
+        Some(AABB::new(min, max))
+    }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.material.clone());
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, _: &mut RenderRng) -> f64 {
+        let ray = Ray::new(origin, direction, time);
+        match self
+            .buffers
+            .intersect(self.index, &ray, MIN_INTERSECTION_DISTANCE, f64::INFINITY)
+        {
+            Some((t, _)) => area_pdf(
+                t.powi(2) * direction.magnitude_squared(),
+                direction,
+                self.buffers.geometric_normal(self.index),
+                self.buffers.area(self.index),
+            ),
+            None => 0.0,
+        }
+    }
+
+    fn sample_direction(&self, origin: Point, _: f64, sampler: &mut dyn Sampler) -> Vector {
+        self.buffers.sample_point(self.index, sampler.get_2d()) - origin
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        let indices = self.buffers.indices[self.index].map(|index| index as usize);
+
+        ObjectDescription::Triangle {
+            vertices: self.buffers.vertices(self.index),
+            normals: (!self.buffers.normals.is_empty())
+                .then(|| indices.map(|i| self.buffers.normals[i])),
+            uvs: (!self.buffers.uvs.is_empty()).then(|| indices.map(|i| self.buffers.uvs[i])),
+            colors: (!self.buffers.colors.is_empty())
+                .then(|| indices.map(|i| self.buffers.colors[i])),
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
+}
+
+struct MeshNode {
+    aabb: AABB,
+    // Leaves hold count triangles from start in the BVH's triangle order. Inner nodes have
+    // their first child straight after them & the second at start
+    start: u32,
+    count: u32,
+    // The axis inner nodes are split along, for visiting the nearer child first
+    axis: u8,
+}
+
+// All the triangles of a mesh under one BVH, so the scene only sees a single object
+pub struct TriangleMesh {
+    buffers: Arc<MeshBuffers>,
+    material: Arc<dyn Material>,
+    nodes: Vec<MeshNode>,
+    // Triangle indices, ordered so each leaf's are together
+    triangles: Vec<u32>,
+    // Running total of the triangles' areas, for picking them in proportion to their area
+    area_cdf: Vec<f64>,
+}
+
+impl TriangleMesh {
+    pub fn new(buffers: Arc<MeshBuffers>, material: Arc<dyn Material>) -> Self {
+        let bounds: Vec<(Point, Point)> = (0..buffers.triangle_count())
+            .map(|triangle| buffers.bounds(triangle))
+            .collect();
+        let mut triangles: Vec<u32> = (0..buffers.triangle_count() as u32).collect();
+        let mut nodes = vec![];
+        if !triangles.is_empty() {
+            build_node(&mut nodes, &mut triangles, 0, &bounds, 0);
+        }
+
+        let area_cdf = (0..buffers.triangle_count())
+            .scan(0.0, |total, triangle| {
+                *total += buffers.area(triangle);
+                Some(*total)
+            })
+            .collect();
+
+        Self {
+            buffers,
+            material,
+            nodes,
+            triangles,
+            area_cdf,
+        }
+    }
+
+    fn total_area(&self) -> f64 {
+        self.area_cdf.last().copied().unwrap_or(0.0)
+    }
+
+    // The nearest triangle hit, its t & barycentric coordinates
+    fn closest(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(usize, f64, [f64; 3])> {
+        let mut closest = None;
+        self.traverse(ray, t_min, t_max, |triangle, t, barycentrics| {
+            closest = Some((triangle, t, barycentrics));
+            t
+        });
+
+        closest
+    }
+
+    // Visits the triangles the ray hits, nearer ones first where it's cheap to. Visit returns
+    // the t_max to carry on with, so the closest hit can narrow the search
+    fn traverse(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        mut t_max: f64,
+        mut visit: impl FnMut(usize, f64, [f64; 3]) -> f64,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = [0u32; MAX_BVH_DEPTH + 1];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let index = stack[stack_len];
+            let node = &self.nodes[index as usize];
+            stats::count_aabb_test();
+            if !node.aabb.hit(ray, t_min, t_max) {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.start as usize;
+                for &triangle in &self.triangles[start..start + node.count as usize] {
+                    let triangle = triangle as usize;
+                    if let Some((t, barycentrics)) =
+                        self.buffers.intersect(triangle, ray, t_min, t_max)
+                    {
+                        t_max = visit(triangle, t, barycentrics);
+                    }
+                }
+            } else {
+                // Pushed so the child nearer the ray's origin is popped first
+                let first = index + 1;
+                let second = node.start;
+                let (near, far) = match ray.direction()[node.axis as usize] < 0.0 {
+                    true => (second, first),
+                    false => (first, second),
+                };
+                stack[stack_len] = far;
+                stack[stack_len + 1] = near;
+                stack_len += 2;
+            }
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _: &mut RenderRng) -> Option<HitRecord> {
+        let (triangle, t, barycentrics) = self.closest(ray, t_min, t_max)?;
+
+        Some(
+            self.buffers
+                .hit_record(triangle, t, barycentrics, ray, self.material.clone()),
+        )
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
+        self.nodes.first().map(|root| root.aabb.clone())
+    }
+
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.material.clone());
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f64, _: &mut RenderRng) -> f64 {
+        if self.total_area() <= 0.0 {
+            return 0.0;
+        }
+
+        // Every point along the ray could have been sampled, not just the closest, so a mesh
+        // which isn't convex or planar is seen more than once in some directions
+        let ray = Ray::new(origin, direction, time);
+        let mut pdf = 0.0;
+        self.traverse(
+            &ray,
+            MIN_INTERSECTION_DISTANCE,
+            f64::INFINITY,
+            |triangle, t, _| {
+                pdf += area_pdf(
+                    t.powi(2) * direction.magnitude_squared(),
+                    direction,
+                    self.buffers.geometric_normal(triangle),
+                    self.total_area(),
+                );
+                f64::INFINITY
+            },
+        );
+
+        pdf
+    }
+
+    fn sample_direction(&self, origin: Point, _: f64, sampler: &mut dyn Sampler) -> Vector {
+        if self.area_cdf.is_empty() {
+            return Vector::new(1.0, 0.0, 0.0);
+        }
+
+        let target = sampler.get_1d() * self.total_area();
+        let triangle = self
+            .area_cdf
+            .partition_point(|&total| total <= target)
+            .min(self.area_cdf.len() - 1);
+
+        self.buffers.sample_point(triangle, sampler.get_2d()) - origin
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::TriangleMesh {
+            positions: self.buffers.positions.clone(),
+            normals: self.buffers.normals.clone(),
+            uvs: self.buffers.uvs.clone(),
+            colors: self.buffers.colors.clone(),
+            indices: self.buffers.indices.clone(),
+            material: exporter.material(self.material.as_ref()),
+        }
+    }
+}
+
+// Splits a polygon into triangles of its corners by clipping off ears, so concave polygons work
+// too. The triangles wind the same way as the polygon
+pub fn triangulate(corners: &[Point]) -> Vec<[usize; 3]> {
+    let count = corners.len();
+    if count == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method, which is robust for concave & slightly non planar polygons
+    let normal: Vector = (0..count)
+        .map(|i| {
+            let (a, b) = (corners[i], corners[(i + 1) % count]);
+            Vector::new(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            )
+        })
+        .sum();
+    // Positive where a, b, c turn the same way as the polygon
+    let turn = |a: usize, b: usize, c: usize| {
+        (corners[b] - corners[a])
+            .cross(&(corners[c] - corners[b]))
+            .dot(&normal)
+    };
+
+    let mut remaining: Vec<usize> = (0..count).collect();
+    let mut triangles = vec![];
+    while remaining.len() > 3 {
+        let len = remaining.len();
+        let corner = |i: usize| remaining[i % len];
+        // A convex corner whose triangle has none of the other corners in or on it
+        let ear = (0..len).find(|&i| {
+            let (a, b, c) = (corner(i + len - 1), corner(i), corner(i + 1));
+            turn(a, b, c) > 0.0
+                && !remaining.iter().any(|&p| {
+                    ![a, b, c].contains(&p)
+                        && turn(a, b, p) >= 0.0
+                        && turn(b, c, p) >= 0.0
+                        && turn(c, a, p) >= 0.0
+                })
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([corner(i + len - 1), corner(i), corner(i + 1)]);
+                remaining.remove(i);
+            }
+            // Degenerate polygons have no ears, the rest is fanned out instead
+            None => break,
+        }
+    }
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+
+    triangles
+}
+
+fn pad(min: Point, max: Point) -> (Point, Point) {
+    let mut min = min;
+    let mut max = max;
+    for axis in 0..3 {
+        let missing = MIN_BOX_THICKNESS - (max[axis] - min[axis]);
+        if missing > 0.0 {
+            min[axis] -= missing / 2.0;
+            max[axis] += missing / 2.0;
+        }
+    }
+
+    (min, max)
+}
+
+fn surface_area(min: Point, max: Point) -> f64 {
+    let size = max - min;
+
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
+
+// Builds the node over the triangles & everything under it, splitting where the surface area
+// heuristic estimates rays will be cheapest to trace. Start is where the triangles are in the
+// BVH's triangle order
+fn build_node(
+    nodes: &mut Vec<MeshNode>,
+    triangles: &mut [u32],
+    start: usize,
+    bounds: &[(Point, Point)],
+    depth: usize,
+) {
+    let node_bounds = |triangles: &[u32]| {
+        triangles.iter().fold(
+            (
+                Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min, max), &triangle| {
+                let (triangle_min, triangle_max) = bounds[triangle as usize];
+                (min.inf(&triangle_min), max.sup(&triangle_max))
+            },
+        )
+    };
+    let centroid = |triangle: u32| {
+        let (min, max) = bounds[triangle as usize];
+        nalgebra::center(&min, &max)
+    };
+
+    let (min, max) = node_bounds(triangles);
+    let (padded_min, padded_max) = pad(min, max);
+    let index = nodes.len();
+    nodes.push(MeshNode {
+        aabb: AABB::new(padded_min, padded_max),
+        start: start as u32,
+        count: triangles.len() as u32,
+        axis: 0,
+    });
+
+    if triangles.len() == 1 || depth >= MAX_BVH_DEPTH {
+        return;
+    }
+
+    let (centroid_min, centroid_max) = triangles.iter().fold(
+        (
+            Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(min, max), &triangle| (min.inf(&centroid(triangle)), max.sup(&centroid(triangle))),
+    );
+    let axis = (centroid_max - centroid_min).iamax();
+    let extent = centroid_max[axis] - centroid_min[axis];
+    if extent <= 0.0 {
+        // Every centroid is in the same place, so there's nothing to split them by
+        return;
+    }
+    let bin = |triangle: u32| {
+        (((centroid(triangle)[axis] - centroid_min[axis]) / extent * SAH_BINS as f64) as usize)
+            .min(SAH_BINS - 1)
+    };
+
+    let mut bin_counts = [0usize; SAH_BINS];
+    let mut bin_bounds = [(
+        Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+    ); SAH_BINS];
+    for &triangle in triangles.iter() {
+        let bin = bin(triangle);
+        let (triangle_min, triangle_max) = bounds[triangle as usize];
+        bin_counts[bin] += 1;
+        bin_bounds[bin] = (
+            bin_bounds[bin].0.inf(&triangle_min),
+            bin_bounds[bin].1.sup(&triangle_max),
+        );
+    }
+
+    // Cost of splitting after each bin, as the area of each side times the triangles in it
+    let side_cost = |bins: &mut dyn Iterator<Item = usize>| {
+        let mut costs = [0.0; SAH_BINS];
+        let mut count = 0;
+        let mut side_min = Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut side_max = Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for bin in bins {
+            count += bin_counts[bin];
+            side_min = side_min.inf(&bin_bounds[bin].0);
+            side_max = side_max.sup(&bin_bounds[bin].1);
+            costs[bin] = match count {
+                0 => 0.0,
+                count => surface_area(side_min, side_max) * count as f64,
+            };
+        }
+        costs
+    };
+    let below = side_cost(&mut (0..SAH_BINS));
+    let above = side_cost(&mut (0..SAH_BINS).rev());
+    let (split, split_cost) = (1..SAH_BINS)
+        .map(|split| (split, below[split - 1] + above[split]))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap();
+
+    let leaf_cost = surface_area(min, max) * triangles.len() as f64;
+    if triangles.len() <= MAX_LEAF_TRIANGLES && split_cost >= leaf_cost {
+        return;
+    }
+
+    let mut middle = partition(triangles, |&triangle| bin(triangle) < split);
+    if middle == 0 || middle == triangles.len() {
+        // Every triangle fell on one side, so split them in half by centroid instead
+        middle = triangles.len() / 2;
+        triangles.select_nth_unstable_by(middle, |&a, &b| {
+            centroid(a)[axis].total_cmp(&centroid(b)[axis])
+        });
+    }
+
+    let (first, second) = triangles.split_at_mut(middle);
+    build_node(nodes, first, start, bounds, depth + 1);
+    let second_index = nodes.len();
+    build_node(nodes, second, start + middle, bounds, depth + 1);
+
+    nodes[index].start = second_index as u32;
+    nodes[index].count = 0;
+    nodes[index].axis = axis as u8;
+}
+
+// Moves the items matching the predicate to the front, returning how many there are
+fn partition<T>(items: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
+    let mut matching = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(i, matching);
+            matching += 1;
+        }
+    }
+
+    matching
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        hittable::{Face, Hittable},
+        material::Lambertian,
+        math::{random_point, Color, Point, RenderRng, Vector},
+        pdf::{Pdf, SpherePdf},
+        ray::Ray,
+        sampler::IndependentSampler,
+    };
+
+    use super::{triangulate, MeshBuffers, Triangle, TriangleMesh};
+
+    fn material() -> Arc<Lambertian> {
+        Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    pub fn rays_through_shared_edges_never_slip_between_triangles() {
+        // A fan of triangles around the center of a square, hit exactly along their edges
+        let positions = vec![
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 0.0, 0.0),
+            Point::new(1.0, 1.0, 0.0),
+            Point::new(0.0, 1.0, 0.0),
+            Point::new(0.5, 0.5, 0.0),
+        ];
+        let indices = vec![[0, 1, 4], [1, 2, 4], [2, 3, 4], [3, 0, 4]];
+        let buffers = MeshBuffers::new(positions, vec![], vec![], vec![], indices).unwrap();
+        let mesh = TriangleMesh::new(Arc::new(buffers), material());
+        let mut rng = RenderRng::seed_from_u64(0);
+
+        for i in 0..=64 {
+            let s = i as f64 / 64.0;
+            for target in [Point::new(s, s, 0.0), Point::new(s, 1.0 - s, 0.0)] {
+                let origin = Point::new(0.3, 0.7, 2.0);
+                let ray = Ray::new(origin, target - origin, 0.0);
+
+                assert!(
+                    mesh.hit(&ray, 0.0, f64::INFINITY, &mut rng).is_some(),
+                    "{target}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn normals_uvs_and_colors_are_interpolated() {
+        let normals = vec![
+            Vector::new(-1.0, 0.0, 1.0).normalize(),
+            Vector::new(1.0, 0.0, 1.0).normalize(),
+            Vector::new(0.0, 1.0, 1.0).normalize(),
+        ];
+        let buffers = MeshBuffers::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(0.0, 1.0, 0.0),
+            ],
+            normals.clone(),
+            vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]],
+            vec![
+                Color::new(1.0, 0.0, 0.0),
+                Color::new(0.0, 1.0, 0.0),
+                Color::new(0.0, 0.0, 1.0),
+            ],
+            vec![[0, 1, 2]],
+        )
+        .unwrap();
+        let triangle = Triangle::new(Arc::new(buffers), 0, material());
+        let mut rng = RenderRng::seed_from_u64(0);
+
+        // At a vertex the normal, uv & color are that vertex's
+        let origin = Point::new(1.0, 0.0, 1.0);
+        let ray = Ray::new(origin, Vector::new(0.0, 0.0, -1.0), 0.0);
+        let hit = triangle.hit(&ray, 0.0, f64::INFINITY, &mut rng).unwrap();
+        assert!(
+            (hit.normal - normals[1]).magnitude() < 1e-9,
+            "{}",
+            hit.normal
+        );
+        assert!((hit.u - 1.0).abs() < 1e-9 && hit.v.abs() < 1e-9);
+        assert!((hit.vertex_color.unwrap() - Color::new(0.0, 1.0, 0.0)).magnitude() < 1e-9);
+
+        let ray = Ray::new(
+            Point::new(0.25, 0.25, 1.0),
+            Vector::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let hit = triangle.hit(&ray, 0.0, f64::INFINITY, &mut rng).unwrap();
+        assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.25).abs() < 1e-9);
+        assert!((hit.vertex_color.unwrap() - Color::new(0.5, 0.25, 0.25)).magnitude() < 1e-9);
+        assert!((hit.normal.magnitude() - 1.0).abs() < 1e-9);
+        assert_eq!(Face::Front, hit.face);
+
+        // From behind, the shading normal is flipped along with the face
+        let ray = Ray::new(
+            Point::new(0.25, 0.25, -1.0),
+            Vector::new(0.0, 0.0, 1.0),
+            0.0,
+        );
+        let hit = triangle.hit(&ray, 0.0, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(Face::Back, hit.face);
+        assert!(hit.normal.z < 0.0);
+    }
+
+    #[test]
+    pub fn unusable_normals_fall_back_to_the_geometric_normal() {
+        let positions = vec![
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 0.0, 0.0),
+            Point::new(0.0, 1.0, 0.0),
+        ];
+        let ray = Ray::new(
+            Point::new(0.25, 0.25, 1.0),
+            Vector::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let mut rng = RenderRng::seed_from_u64(0);
+
+        for normal in [Vector::zeros(), Vector::new(f64::NAN, 0.0, 1.0)] {
+            let buffers = MeshBuffers::new(
+                positions.clone(),
+                vec![normal; 3],
+                vec![],
+                vec![],
+                vec![[0, 1, 2]],
+            )
+            .unwrap();
+            let triangle = Triangle::new(Arc::new(buffers), 0, material());
+
+            let hit = triangle.hit(&ray, 0.0, f64::INFINITY, &mut rng).unwrap();
+            assert_eq!(Vector::new(0.0, 0.0, 1.0), hit.normal);
+        }
+    }
+
+    #[test]
+    pub fn concave_polygons_are_triangulated() {
+        // An L of three unit squares, with the inside corner at (1, 1)
+        let corners = [
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(2.0, 0.0, 0.0),
+            Point::new(2.0, 1.0, 0.0),
+            Point::new(1.0, 1.0, 0.0),
+            Point::new(1.0, 2.0, 0.0),
+            Point::new(0.0, 2.0, 0.0),
+        ];
+        let triangles = triangulate(&corners);
+
+        assert_eq!(4, triangles.len());
+        let mut area = 0.0;
+        for [a, b, c] in triangles {
+            let normal = (corners[b] - corners[a]).cross(&(corners[c] - corners[a]));
+            // All wound the same way as the polygon
+            assert!(normal.z >= 0.0);
+            area += normal.z / 2.0;
+        }
+        assert!((area - 3.0).abs() < 1e-9, "{area}");
+    }
+
+    #[test]
+    pub fn mesh_hits_the_same_as_its_triangles() {
+        let mut rng = RenderRng::seed_from_u64(1);
+        let mut positions = vec![];
+        let mut indices = vec![];
+        for i in 0..500 {
+            let center = random_point(&mut rng, -10.0..10.0);
+            for _ in 0..3 {
+                positions.push(center + random_point(&mut rng, -1.0..1.0).coords);
+            }
+            indices.push([3 * i, 3 * i + 1, 3 * i + 2]);
+        }
+        let buffers =
+            Arc::new(MeshBuffers::new(positions, vec![], vec![], vec![], indices).unwrap());
+        let mesh = TriangleMesh::new(buffers.clone(), material());
+        let triangles: Vec<Triangle> = (0..buffers.triangle_count())
+            .map(|i| Triangle::new(buffers.clone(), i, material()))
+            .collect();
+
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let origin = random_point(&mut rng, -15.0..15.0);
+            let direction = random_point(&mut rng, -10.0..10.0) - origin;
+            let ray = Ray::new(origin, direction, 0.0);
+
+            let closest = triangles
+                .iter()
+                .filter_map(|triangle| triangle.hit(&ray, 0.001, f64::INFINITY, &mut rng))
+                .map(|hit| hit.t)
+                .min_by(f64::total_cmp);
+            let mesh_hit = mesh.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+            assert_eq!(closest, mesh_hit.map(|hit| hit.t));
+            hits += closest.is_some() as usize;
+        }
+        assert!(hits > 100, "{hits}");
+
+        // Directions sampled towards the mesh land on it
+        let mut sampler = IndependentSampler::new(rng.gen());
+        for _ in 0..100 {
+            let origin = Point::new(0.0, 20.0, 0.0);
+            let direction = mesh.sample_direction(origin, 0.0, &mut sampler);
+            assert!(mesh.pdf_value(origin, direction, 0.0, &mut rng) > 0.0);
+        }
+    }
+
+    #[test]
+    pub fn closed_mesh_pdfs_integrate_to_one() {
+        // A cube, so most directions which hit it pass through two faces
+        let positions = (0..8)
+            .map(|i| Point::new((i & 1) as f64, (i >> 1 & 1) as f64, (i >> 2 & 1) as f64))
+            .collect();
+        let indices = vec![
+            [0, 1, 3],
+            [0, 3, 2],
+            [4, 6, 7],
+            [4, 7, 5],
+            [0, 4, 5],
+            [0, 5, 1],
+            [2, 3, 7],
+            [2, 7, 6],
+            [0, 2, 6],
+            [0, 6, 4],
+            [1, 5, 7],
+            [1, 7, 3],
+        ];
+        let buffers = MeshBuffers::new(positions, vec![], vec![], vec![], indices).unwrap();
+        let mesh = TriangleMesh::new(Arc::new(buffers), material());
+        let mut rng = RenderRng::seed_from_u64(0);
+        let mut sampler = IndependentSampler::new(rng.gen());
+        let origin = Point::new(0.5, 0.5, 1.5);
+        let count = 100_000;
+
+        let mut integral = 0.0;
+        for _ in 0..count {
+            let direction = SpherePdf.generate(&mut sampler);
+            integral += mesh.pdf_value(origin, direction, 0.0, &mut rng)
+                / SpherePdf.value(&direction, &mut rng);
+        }
+
+        assert!((integral / count as f64 - 1.0).abs() < 0.03, "{integral}");
+    }
+}
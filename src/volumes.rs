@@ -1,16 +1,19 @@
 use std::sync::Arc;
 
+use rand::Rng;
+
 use crate::{
     hittable::{HitRecord, Hittable},
     material::{Isotropic, Material},
-    math::{Color, Vector},
+    math::{Color, RenderRng, Vector},
+    scene_file::{ObjectDescription, SceneExporter},
     texture::Texture,
     MIN_INTERSECTION_DISTANCE,
 };
 
 pub struct ConstantVolume {
     boundary: Arc<dyn Hittable>,
-    phase_function: Arc<dyn Material>,
+    phase_function: Arc<Isotropic>,
     negative_inverse_density: f64,
 }
 
@@ -40,19 +43,14 @@ impl Hittable for ConstantVolume {
         ray: &crate::ray::Ray,
         t_min: f64,
         t_max: f64,
+        rng: &mut RenderRng,
     ) -> Option<crate::hittable::HitRecord> {
-        let mut hit1 = match self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY) {
-            Some(hit) => hit,
-            None => return None,
-        };
+        let mut hit1 = self
+            .boundary
+            .hit(ray, f64::NEG_INFINITY, f64::INFINITY, rng)?;
         let mut hit2 =
-            match self
-                .boundary
-                .hit(ray, hit1.t + MIN_INTERSECTION_DISTANCE, f64::INFINITY)
-            {
-                Some(hit) => hit,
-                None => return None,
-            };
+            self.boundary
+                .hit(ray, hit1.t + MIN_INTERSECTION_DISTANCE, f64::INFINITY, rng)?;
 
         hit1.t = hit1.t.max(t_min);
         hit2.t = hit2.t.min(t_max);
@@ -66,7 +64,7 @@ impl Hittable for ConstantVolume {
         let ray_length = ray.direction().magnitude();
         let distance_inside_boundary = (hit2.t - hit1.t) * ray_length;
         let hit_distance =
-            self.negative_inverse_density * rand::random::<f64>().log(std::f64::consts::E);
+            self.negative_inverse_density * rng.gen::<f64>().log(std::f64::consts::E);
 
         if hit_distance > distance_inside_boundary {
             return None;
@@ -86,4 +84,17 @@ impl Hittable for ConstantVolume {
     fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<crate::aabb::AABB> {
         self.boundary.bounding_box(start_time, end_time)
     }
+
+    // Only the phase function is ever hit, the boundary just shapes the volume
+    fn materials(&self, materials: &mut Vec<Arc<dyn Material>>) {
+        materials.push(self.phase_function.clone());
+    }
+
+    fn describe(&self, exporter: &mut SceneExporter) -> ObjectDescription {
+        ObjectDescription::ConstantVolume {
+            density: -1.0 / self.negative_inverse_density,
+            albedo: exporter.texture(self.phase_function.albedo()),
+            boundary: Box::new(self.boundary.describe(exporter)),
+        }
+    }
 }